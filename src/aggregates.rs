@@ -0,0 +1,398 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde_derive::Deserialize;
+use serde_json::{json, Map, Value};
+use sonr::errors::Result;
+use sonr::reactor::{Reaction, Reactor};
+use sonr::sync::signal::{ReactiveSignalReceiver, SignalReceiver};
+
+use crate::filter::glob;
+use crate::messages::{Message, MessageType, Severity};
+use crate::publisher::Publisher;
+use crate::timer::ticker;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AggregateConfig {
+    // The channel the aggregates are published on
+    pub channel: String,
+    // Channel patterns, `*` matches any number of characters
+    pub sources: Vec<String>,
+    // Path to the numeric value, as in filters
+    #[serde(default = "default_field")]
+    pub field: String,
+    // min, max, avg, sum, count or a percentile such as p95
+    pub functions: Vec<String>,
+    // Seconds
+    pub window: u64,
+    // Publish every `slide` seconds over the last `window` seconds,
+    // instead of once at the end of every window
+    #[serde(default)]
+    pub slide: Option<u64>,
+    // A label to aggregate each of its values separately
+    #[serde(default)]
+    pub group_by: Option<String>,
+}
+
+fn default_field() -> String {
+    "payload.value".into()
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Function {
+    Min,
+    Max,
+    Avg,
+    Sum,
+    Count,
+    Percentile(f64),
+}
+
+impl Function {
+    fn parse(name: &str) -> std::result::Result<Function, String> {
+        let function = match name {
+            "min" => Function::Min,
+            "max" => Function::Max,
+            "avg" => Function::Avg,
+            "sum" => Function::Sum,
+            "count" => Function::Count,
+            p if p.starts_with('p') => match p[1..].parse::<f64>() {
+                Ok(p) if p > 0.0 && p <= 100.0 => Function::Percentile(p),
+                _ => return Err(format!("invalid percentile \"{}\"", name)),
+            },
+            _ => return Err(format!("unknown function \"{}\"", name)),
+        };
+        Ok(function)
+    }
+
+    // `sorted` is never empty
+    fn apply(self, sorted: &[f64]) -> f64 {
+        let sum = || sorted.iter().sum::<f64>();
+        match self {
+            Function::Min => sorted[0],
+            Function::Max => sorted[sorted.len() - 1],
+            Function::Avg => sum() / sorted.len() as f64,
+            Function::Sum => sum(),
+            Function::Count => sorted.len() as f64,
+            // Nearest rank
+            Function::Percentile(p) => {
+                let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+                sorted[rank.max(1) - 1]
+            }
+        }
+    }
+}
+
+struct Aggregate {
+    channel: String,
+    sources: Vec<String>,
+    field: Vec<String>,
+    functions: Vec<(String, Function)>,
+    window: Duration,
+    period: Duration,
+    sliding: bool,
+    group_by: Option<String>,
+    // Samples per label value
+    groups: HashMap<Option<String>, VecDeque<(Instant, f64)>>,
+    next_publish: Instant,
+}
+
+impl Aggregate {
+    fn new(config: &AggregateConfig) -> std::result::Result<Aggregate, String> {
+        if config.window == 0 || config.slide == Some(0) {
+            return Err("window and slide must be greater than 0".into());
+        }
+        if config.functions.is_empty() {
+            return Err("no functions".into());
+        }
+        let functions = config
+            .functions
+            .iter()
+            .map(|name| Function::parse(name).map(|f| (name.clone(), f)))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        let window = Duration::from_secs(config.window);
+        let period = config.slide.map(Duration::from_secs).unwrap_or(window);
+        Ok(Aggregate {
+            channel: config.channel.clone(),
+            sources: config.sources.clone(),
+            field: config.field.split('.').map(String::from).collect(),
+            functions,
+            window,
+            period,
+            sliding: config.slide.is_some(),
+            group_by: config.group_by.clone(),
+            groups: HashMap::new(),
+            next_publish: Instant::now() + period,
+        })
+    }
+
+    fn add(&mut self, message: &Message) {
+        if message.channel() == self.channel || !self.sources.iter().any(|s| glob(s, message.channel())) {
+            return;
+        }
+        let value = match message.lookup(&self.field).as_f64() {
+            Some(value) if value.is_finite() => value,
+            _ => return,
+        };
+        let group = match self.group_by {
+            Some(ref label) => match message.lookup(&["labels".into(), label.clone()]) {
+                Value::String(group) => Some(group),
+                Value::Null => return,
+                group => Some(group.to_string()),
+            },
+            None => None,
+        };
+
+        self.groups
+            .entry(group)
+            .or_default()
+            .push_back((Instant::now(), value));
+    }
+
+    // The aggregates to publish, once a period
+    fn due(&mut self) -> Vec<Message> {
+        let mut messages = Vec::new();
+        if Instant::now() < self.next_publish {
+            return messages;
+        }
+        self.next_publish += self.period;
+
+        let window = self.window;
+        for (group, samples) in self.groups.iter_mut() {
+            while samples.front().is_some_and(|(at, _)| at.elapsed() > window) {
+                samples.pop_front();
+            }
+            if samples.is_empty() {
+                continue;
+            }
+
+            let mut sorted = samples.iter().map(|(_, v)| *v).collect::<Vec<_>>();
+            sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+            let mut payload = Map::new();
+            payload.insert("name".into(), json!(self.channel));
+            payload.insert("window".into(), json!(window.as_secs()));
+            if let (Some(label), Some(group)) = (&self.group_by, group) {
+                payload.insert("labels".into(), json!({ label.as_str(): group }));
+            }
+            for (name, function) in &self.functions {
+                payload.insert(name.clone(), json!(function.apply(&sorted)));
+            }
+
+            let message = Message::new(&self.channel, Value::Object(payload).into(), MessageType::Status, Severity::Info);
+            messages.push(message);
+
+            if !self.sliding {
+                samples.clear();
+            }
+        }
+        self.groups.retain(|_, samples| !samples.is_empty());
+        messages
+    }
+}
+
+// Channels computed from other channels, published like any other message
+pub struct Aggregates {
+    receiver: ReactiveSignalReceiver<Arc<Message>>,
+    ticker: Option<ReactiveSignalReceiver<()>>,
+    publisher: Publisher,
+    aggregates: Vec<Aggregate>,
+}
+
+impl Aggregates {
+    pub fn new(receiver: SignalReceiver<Arc<Message>>, publisher: Publisher, configs: &[AggregateConfig]) -> Result<Self> {
+        let mut aggregates = Vec::new();
+        for config in configs {
+            let aggregate = Aggregate::new(config).map_err(|reason| {
+                let reason = format!("invalid aggregate \"{}\": {}", config.channel, reason);
+                io::Error::new(io::ErrorKind::InvalidInput, reason)
+            })?;
+            aggregates.push(aggregate);
+        }
+
+        let ticker = match aggregates.is_empty() {
+            true => None,
+            false => Some(ticker(Duration::from_secs(1))?),
+        };
+
+        Ok(Self {
+            receiver: ReactiveSignalReceiver::new(receiver)?,
+            ticker,
+            publisher,
+            aggregates,
+        })
+    }
+}
+
+impl Reactor for Aggregates {
+    type Input = ();
+    type Output = ();
+
+    fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
+        match reaction {
+            Reaction::Event(event) => {
+                if let Some(ref ticker) = self.ticker {
+                    if event.token() == ticker.token() {
+                        while let Ok(()) = ticker.try_recv() {}
+                        for aggregate in self.aggregates.iter_mut() {
+                            for message in aggregate.due() {
+                                self.publisher.publish(message);
+                            }
+                        }
+                        return Reaction::Continue;
+                    }
+                }
+
+                if event.token() == self.receiver.token() {
+                    while let Ok(message) = self.receiver.try_recv() {
+                        for aggregate in self.aggregates.iter_mut() {
+                            aggregate.add(&message);
+                        }
+                    }
+                    return Reaction::Continue;
+                }
+
+                event.into()
+            }
+            Reaction::Value(()) | Reaction::Continue => Reaction::Continue,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(functions: &[&str], slide: Option<u64>, group_by: Option<&str>) -> AggregateConfig {
+        AggregateConfig {
+            channel: "web.cpu".into(),
+            sources: vec!["web*".into()],
+            field: default_field(),
+            functions: functions.iter().map(|f| f.to_string()).collect(),
+            window: 60,
+            slide,
+            group_by: group_by.map(String::from),
+        }
+    }
+
+    fn gauge(channel: &str, value: f64, env: &str) -> Message {
+        let payload = json!({ "name": "cpu", "value": value, "labels": { "env": env } });
+        Message::new(channel, payload.into(), MessageType::Gauge, Severity::Info)
+    }
+
+    fn field(message: &Message, name: &str) -> Value {
+        message.lookup(&["payload".into(), name.into()])
+    }
+
+    // Pretend the period is over
+    fn elapse(aggregate: &mut Aggregate) {
+        aggregate.next_publish = Instant::now();
+    }
+
+    #[test]
+    fn applies_functions() {
+        let sorted = [1.0, 2.0, 3.0, 4.0];
+        assert_eq!(Function::Min.apply(&sorted), 1.0);
+        assert_eq!(Function::Max.apply(&sorted), 4.0);
+        assert_eq!(Function::Avg.apply(&sorted), 2.5);
+        assert_eq!(Function::Sum.apply(&sorted), 10.0);
+        assert_eq!(Function::Count.apply(&sorted), 4.0);
+    }
+
+    #[test]
+    fn percentiles() {
+        let sorted = (1..=10).map(f64::from).collect::<Vec<_>>();
+        assert_eq!(Function::parse("p50").unwrap().apply(&sorted), 5.0);
+        assert_eq!(Function::parse("p95").unwrap().apply(&sorted), 10.0);
+        assert_eq!(Function::parse("p100").unwrap().apply(&sorted), 10.0);
+        assert_eq!(Function::parse("p0.1").unwrap().apply(&sorted), 1.0);
+
+        // A single sample is every percentile
+        for p in &["p1", "p50", "p99", "p100"] {
+            assert_eq!(Function::parse(p).unwrap().apply(&[7.0]), 7.0);
+        }
+
+        for invalid in &["p0", "p101", "p", "px", "median"] {
+            assert!(Function::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn publishes_nothing_for_empty_windows() {
+        let mut aggregate = Aggregate::new(&config(&["p50"], None, None)).unwrap();
+        elapse(&mut aggregate);
+        assert!(aggregate.due().is_empty());
+
+        // Not a source, the aggregate itself or not a number
+        aggregate.add(&gauge("db1", 1.0, "prod"));
+        aggregate.add(&gauge("web.cpu", 1.0, "prod"));
+        let text = Message::new("web1", json!({ "value": "high" }).into(), MessageType::Status, Severity::Info);
+        aggregate.add(&text);
+        elapse(&mut aggregate);
+        assert!(aggregate.due().is_empty());
+    }
+
+    #[test]
+    fn tumbling_windows_roll_over() {
+        let mut aggregate = Aggregate::new(&config(&["count", "max"], None, None)).unwrap();
+        aggregate.add(&gauge("web1", 3.0, "prod"));
+        aggregate.add(&gauge("web2", 5.0, "prod"));
+        // Not due yet
+        assert!(aggregate.due().is_empty());
+
+        elapse(&mut aggregate);
+        let published = aggregate.due();
+        assert_eq!(published.len(), 1);
+        assert_eq!(published[0].channel(), "web.cpu");
+        assert_eq!((field(&published[0], "count"), field(&published[0], "max")), (json!(2.0), json!(5.0)));
+
+        // The next window starts empty
+        aggregate.add(&gauge("web1", 1.0, "prod"));
+        elapse(&mut aggregate);
+        let published = aggregate.due();
+        assert_eq!((field(&published[0], "count"), field(&published[0], "max")), (json!(1.0), json!(1.0)));
+    }
+
+    #[test]
+    fn sliding_windows_evict_old_samples() {
+        let mut aggregate = Aggregate::new(&config(&["count"], Some(10), None)).unwrap();
+        let old = Instant::now().checked_sub(Duration::from_secs(61)).unwrap();
+        aggregate.groups.entry(None).or_default().push_back((old, 1.0));
+        aggregate.add(&gauge("web1", 2.0, "prod"));
+
+        elapse(&mut aggregate);
+        assert_eq!(field(&aggregate.due()[0], "count"), json!(1.0));
+        // Samples within the window are kept for the next slide
+        elapse(&mut aggregate);
+        assert_eq!(field(&aggregate.due()[0], "count"), json!(1.0));
+    }
+
+    #[test]
+    fn groups_by_label() {
+        let mut aggregate = Aggregate::new(&config(&["sum"], None, Some("env"))).unwrap();
+        aggregate.add(&gauge("web1", 1.0, "prod"));
+        aggregate.add(&gauge("web2", 2.0, "prod"));
+        aggregate.add(&gauge("web3", 5.0, "staging"));
+        // Without the label
+        aggregate.add(&Message::new("web4", json!({ "value": 9.0 }).into(), MessageType::Gauge, Severity::Info));
+
+        elapse(&mut aggregate);
+        let mut sums = aggregate
+            .due()
+            .iter()
+            .map(|m| (m.lookup(&["labels".into(), "env".into()]), field(m, "sum")))
+            .collect::<Vec<_>>();
+        sums.sort_by_key(|(env, _)| env.to_string());
+        assert_eq!(sums, vec![(json!("prod"), json!(3.0)), (json!("staging"), json!(5.0))]);
+    }
+
+    #[test]
+    fn rejects_invalid_configs() {
+        assert!(Aggregate::new(&config(&[], None, None)).is_err());
+        assert!(Aggregate::new(&config(&["p50"], Some(0), None)).is_err());
+        assert!(Aggregate::new(&config(&["median"], None, None)).is_err());
+    }
+}
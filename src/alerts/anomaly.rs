@@ -0,0 +1,396 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::info;
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+use sonr::errors::Result;
+use sonr::reactor::{Reaction, Reactor};
+use sonr::sync::signal::{ReactiveSignalReceiver, SignalReceiver};
+
+use super::{alert_msg, AlertState, ALERTS_CHANNEL};
+use crate::messages::{Message, Severity};
+use crate::publisher::Publisher;
+use crate::timer::ticker;
+
+// How often baselines are checked for being idle
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Method {
+    // Mean and standard deviation of the last `window` values
+    ZScore,
+    // Exponentially weighted mean and variance, weighing each new value by `alpha`
+    Ewma,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AnomalyConfig {
+    pub name: String,
+    // No channels means every channel
+    pub channels: Vec<String>,
+    // Path to the numeric value, as in filters
+    pub field: String,
+    pub method: Method,
+    pub window: usize,
+    pub alpha: f64,
+    // Standard deviations from the mean a value can be before it's an anomaly
+    pub threshold: f64,
+    // Values needed before anything is reported
+    pub min_samples: usize,
+    pub severity: Severity,
+    // Seconds without values after which a baseline is forgotten, 0 keeps them
+    pub idle: u64,
+}
+
+impl Default for AnomalyConfig {
+    fn default() -> Self {
+        Self {
+            name: "anomaly".into(),
+            channels: Vec::new(),
+            field: "payload.value".into(),
+            method: Method::ZScore,
+            window: 60,
+            alpha: 0.1,
+            threshold: 3.0,
+            min_samples: 10,
+            severity: Severity::Warning,
+            idle: 3600,
+        }
+    }
+}
+
+impl AnomalyConfig {
+    fn validate(&self) -> std::result::Result<(), String> {
+        match self.method {
+            Method::ZScore if self.window < 2 => return Err("window must be at least 2".into()),
+            Method::Ewma if !(self.alpha > 0.0 && self.alpha <= 1.0) => {
+                return Err("alpha must be greater than 0 and at most 1".into())
+            }
+            _ => {}
+        }
+        if self.threshold.partial_cmp(&0.0) != Some(Ordering::Greater) {
+            return Err("threshold must be greater than 0".into());
+        }
+        Ok(())
+    }
+}
+
+struct Baseline {
+    values: VecDeque<f64>,
+    samples: usize,
+    mean: f64,
+    variance: f64,
+    anomalous: bool,
+    updated: Instant,
+}
+
+impl Baseline {
+    fn new() -> Self {
+        Self {
+            values: VecDeque::new(),
+            samples: 0,
+            mean: 0.0,
+            variance: 0.0,
+            anomalous: false,
+            updated: Instant::now(),
+        }
+    }
+
+    // Mean and standard deviation before adding `value`
+    fn add(&mut self, value: f64, config: &AnomalyConfig) -> Option<(f64, f64)> {
+        let expected = match self.samples >= config.min_samples {
+            true => Some((self.mean, self.variance.sqrt())),
+            false => None,
+        };
+        self.samples += 1;
+        self.updated = Instant::now();
+
+        match config.method {
+            Method::ZScore => {
+                self.values.push_back(value);
+                if self.values.len() > config.window {
+                    self.values.pop_front();
+                }
+                let n = self.values.len() as f64;
+                self.mean = self.values.iter().sum::<f64>() / n;
+                self.variance = self.values.iter().map(|v| (v - self.mean).powi(2)).sum::<f64>() / n;
+            }
+            Method::Ewma if self.samples == 1 => self.mean = value,
+            Method::Ewma => {
+                let diff = value - self.mean;
+                self.mean += config.alpha * diff;
+                self.variance = (1.0 - config.alpha) * (self.variance + config.alpha * diff * diff);
+            }
+        }
+
+        expected
+    }
+}
+
+struct Detector {
+    config: AnomalyConfig,
+    field: Vec<String>,
+    // Per channel and metric name
+    baselines: HashMap<(String, Option<String>), Baseline>,
+}
+
+impl Detector {
+    fn new(config: &AnomalyConfig) -> Self {
+        Self {
+            field: config.field.split('.').map(String::from).collect(),
+            config: config.clone(),
+            baselines: HashMap::new(),
+        }
+    }
+
+    fn observe(&mut self, message: &Message) -> Option<Message> {
+        if !self.config.channels.is_empty() && !self.config.channels.iter().any(|c| c == message.channel()) {
+            return None;
+        }
+        let value = match message.lookup(&self.field).as_f64() {
+            Some(value) if value.is_finite() => value,
+            _ => return None,
+        };
+        let name = match message.lookup(&["payload".into(), "name".into()]) {
+            Value::String(name) => Some(name),
+            _ => None,
+        };
+
+        let config = &self.config;
+        let baseline = self
+            .baselines
+            .entry((message.channel().to_owned(), name.clone()))
+            .or_insert_with(Baseline::new);
+        let (mean, deviation) = baseline.add(value, config)?;
+        let range = config.threshold * deviation;
+        let anomalous = (value - mean).abs() > range;
+        if anomalous == baseline.anomalous {
+            return None;
+        }
+        baseline.anomalous = anomalous;
+
+        let state = match anomalous {
+            true => {
+                info!("Anomaly on channel \"{}\": {}", message.channel(), value);
+                AlertState::Firing
+            }
+            false => AlertState::Resolved,
+        };
+        let details = json!({
+            "channel": message.channel(),
+            "name": name,
+            "value": value,
+            "expected": { "min": mean - range, "max": mean + range },
+        });
+        Some(alert_msg(&config.name, state, config.severity, details))
+    }
+
+    // Forget baselines without values for `idle` seconds, so channels that
+    // come and go don't pile up. Anomalies still firing on them are resolved.
+    fn expire(&mut self) -> Vec<Message> {
+        if self.config.idle == 0 {
+            return Vec::new();
+        }
+        let idle = Duration::from_secs(self.config.idle);
+        let expired = self
+            .baselines
+            .iter()
+            .filter(|(_, baseline)| baseline.updated.elapsed() > idle)
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+
+        let mut resolved = Vec::new();
+        for (channel, name) in expired {
+            let baseline = self.baselines.remove(&(channel.clone(), name.clone()));
+            if baseline.is_some_and(|b| b.anomalous) {
+                let details = json!({ "channel": channel, "name": name, "reason": "idle" });
+                resolved.push(alert_msg(&self.config.name, AlertState::Resolved, self.config.severity, details));
+            }
+        }
+        resolved
+    }
+}
+
+// Reports numeric values far outside of their recent baseline, and when
+// they return to it.
+pub struct Anomalies {
+    receiver: ReactiveSignalReceiver<Arc<Message>>,
+    ticker: Option<ReactiveSignalReceiver<()>>,
+    publisher: Publisher,
+    detectors: Vec<Detector>,
+}
+
+impl Anomalies {
+    pub fn new(receiver: SignalReceiver<Arc<Message>>, publisher: Publisher, configs: &[AnomalyConfig]) -> Result<Self> {
+        let mut detectors = Vec::new();
+        for config in configs {
+            config.validate().map_err(|reason| {
+                let reason = format!("invalid anomaly detection \"{}\": {}", config.name, reason);
+                io::Error::new(io::ErrorKind::InvalidInput, reason)
+            })?;
+            detectors.push(Detector::new(config));
+        }
+
+        let ticker = match detectors.iter().any(|d| d.config.idle > 0) {
+            true => Some(ticker(EXPIRE_INTERVAL)?),
+            false => None,
+        };
+
+        Ok(Self {
+            receiver: ReactiveSignalReceiver::new(receiver)?,
+            ticker,
+            publisher,
+            detectors,
+        })
+    }
+}
+
+impl Reactor for Anomalies {
+    type Input = ();
+    type Output = ();
+
+    fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
+        match reaction {
+            Reaction::Event(event) => {
+                if let Some(ref ticker) = self.ticker {
+                    if event.token() == ticker.token() {
+                        while let Ok(()) = ticker.try_recv() {}
+                        for detector in self.detectors.iter_mut() {
+                            for resolved in detector.expire() {
+                                self.publisher.publish(resolved);
+                            }
+                        }
+                        return Reaction::Continue;
+                    }
+                }
+
+                if event.token() != self.receiver.token() {
+                    return event.into();
+                }
+
+                while let Ok(message) = self.receiver.try_recv() {
+                    if message.channel() == ALERTS_CHANNEL {
+                        continue;
+                    }
+                    for detector in self.detectors.iter_mut() {
+                        if let Some(anomaly) = detector.observe(&message) {
+                            self.publisher.publish(anomaly);
+                        }
+                    }
+                }
+                Reaction::Continue
+            }
+            Reaction::Value(()) | Reaction::Continue => Reaction::Continue,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::MessageType;
+
+    fn config(method: Method) -> AnomalyConfig {
+        AnomalyConfig {
+            method,
+            min_samples: 5,
+            ..AnomalyConfig::default()
+        }
+    }
+
+    fn gauge(channel: &str, value: f64) -> Message {
+        let payload = json!({ "name": "latency", "value": value });
+        Message::new(channel, payload.into(), MessageType::Gauge, Severity::Info)
+    }
+
+    fn state_of(alert: &Message) -> Value {
+        alert.lookup(&["payload".into(), "state".into()])
+    }
+
+    #[test]
+    fn warms_up() {
+        for &method in &[Method::ZScore, Method::Ewma] {
+            let config = config(method);
+            let mut baseline = Baseline::new();
+            for _ in 0..5 {
+                assert!(baseline.add(10.0, &config).is_none());
+            }
+            assert_eq!(baseline.add(10.0, &config), Some((10.0, 0.0)));
+        }
+    }
+
+    #[test]
+    fn z_score_over_window() {
+        let config = AnomalyConfig {
+            window: 2,
+            min_samples: 0,
+            ..AnomalyConfig::default()
+        };
+        let mut baseline = Baseline::new();
+        baseline.add(1.0, &config);
+        baseline.add(3.0, &config);
+        assert_eq!(baseline.add(100.0, &config), Some((2.0, 1.0)));
+        // Only the last two values are kept
+        assert_eq!(baseline.add(0.0, &config), Some((51.5, 48.5)));
+    }
+
+    #[test]
+    fn ewma_weighs_new_values() {
+        let config = AnomalyConfig {
+            method: Method::Ewma,
+            alpha: 0.5,
+            min_samples: 0,
+            ..AnomalyConfig::default()
+        };
+        let mut baseline = Baseline::new();
+        baseline.add(10.0, &config);
+        baseline.add(20.0, &config);
+        assert_eq!((baseline.mean, baseline.variance), (15.0, 25.0));
+    }
+
+    #[test]
+    fn fires_and_resolves_on_threshold() {
+        for &method in &[Method::ZScore, Method::Ewma] {
+            let mut detector = Detector::new(&config(method));
+            for value in [10.0, 11.0, 9.0, 10.0, 11.0, 9.0].iter() {
+                assert!(detector.observe(&gauge("api", *value)).is_none());
+            }
+
+            let alert = detector.observe(&gauge("api", 50.0)).unwrap();
+            assert_eq!(state_of(&alert), "firing");
+            assert_eq!(alert.lookup(&["payload".into(), "value".into()]), 50.0);
+            // Another channel has a baseline of its own
+            assert!(detector.observe(&gauge("db", 50.0)).is_none());
+
+            let alert = detector.observe(&gauge("api", 10.0)).unwrap();
+            assert_eq!(state_of(&alert), "resolved");
+        }
+    }
+
+    #[test]
+    fn forgets_idle_baselines() {
+        let mut detector = Detector::new(&config(Method::ZScore));
+        for value in [10.0, 11.0, 9.0, 10.0, 11.0, 9.0, 50.0].iter() {
+            detector.observe(&gauge("api", *value));
+        }
+        detector.observe(&gauge("db", 1.0));
+        assert!(detector.expire().is_empty());
+
+        let key = ("api".to_owned(), Some("latency".to_owned()));
+        let baseline = detector.baselines.get_mut(&key).unwrap();
+        baseline.updated = Instant::now().checked_sub(Duration::from_secs(3601)).unwrap();
+
+        let resolved = detector.expire();
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(state_of(&resolved[0]), "resolved");
+        assert_eq!(resolved[0].lookup(&["payload".into(), "reason".into()]), "idle");
+        assert!(!detector.baselines.contains_key(&key));
+        assert_eq!(detector.baselines.len(), 1);
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::info;
+use serde_derive::Deserialize;
+use serde_json::json;
+use sonr::errors::Result;
+use sonr::reactor::{Reaction, Reactor};
+use sonr::sync::signal::{ReactiveSignalReceiver, SignalReceiver};
+
+use super::{alert_msg, AlertState};
+use crate::messages::{Message, Severity};
+use crate::publisher::Publisher;
+use crate::timer::{now_millis, ticker};
+
+// Seconds within which something has to be published
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DeadmanConfig {
+    // Per monitor identity, on any channel
+    pub monitors: HashMap<String, u64>,
+    // Per channel, by any monitor
+    pub channels: HashMap<String, u64>,
+    pub severity: Severity,
+}
+
+impl Default for DeadmanConfig {
+    fn default() -> Self {
+        Self {
+            monitors: HashMap::new(),
+            channels: HashMap::new(),
+            severity: Severity::Critical,
+        }
+    }
+}
+
+enum Target {
+    Monitor(String),
+    Channel(String),
+}
+
+struct Watch {
+    target: Target,
+    expected: Duration,
+    last_seen: Instant,
+    last_seen_at: Option<u64>,
+    silent: bool,
+}
+
+impl Watch {
+    fn new(target: Target, expected: Duration) -> Self {
+        Self {
+            target,
+            expected,
+            last_seen: Instant::now(),
+            last_seen_at: None,
+            silent: false,
+        }
+    }
+
+    fn matches(&self, message: &Message) -> bool {
+        match self.target {
+            Target::Monitor(ref identity) => {
+                message.source().and_then(|s| s.identity.as_ref()) == Some(identity)
+            }
+            Target::Channel(ref channel) => message.channel() == channel,
+        }
+    }
+
+    fn alert(&self, state: AlertState, severity: Severity) -> Message {
+        let mut details = json!({
+            "expected_interval": self.expected.as_secs(),
+            "last_seen_at": self.last_seen_at,
+        });
+        match self.target {
+            Target::Monitor(ref identity) => details["monitor"] = json!(identity),
+            Target::Channel(ref channel) => details["channel"] = json!(channel),
+        }
+        alert_msg("silent", state, severity, details)
+    }
+
+    // Re-arms the watch, resolving the alert if it was silent
+    fn seen(&mut self, severity: Severity) -> Option<Message> {
+        self.last_seen = Instant::now();
+        self.last_seen_at = Some(now_millis());
+        if !self.silent {
+            return None;
+        }
+        self.silent = false;
+        Some(self.alert(AlertState::Resolved, severity))
+    }
+
+    // Fires once when the expected interval has passed
+    fn check(&mut self, severity: Severity) -> Option<Message> {
+        if self.silent || self.last_seen.elapsed() <= self.expected {
+            return None;
+        }
+        self.silent = true;
+        match self.target {
+            Target::Monitor(ref identity) => info!("Monitor \"{}\" went silent", identity),
+            Target::Channel(ref channel) => info!("Channel \"{}\" went silent", channel),
+        }
+        Some(self.alert(AlertState::Firing, severity))
+    }
+}
+
+// Raises an alert when a monitor or channel hasn't published within its
+// expected interval, and resolves it once something arrives.
+// Watches start when the server does, so a monitor that never connects
+// is reported as well.
+pub struct Deadman {
+    receiver: ReactiveSignalReceiver<Arc<Message>>,
+    ticker: Option<ReactiveSignalReceiver<()>>,
+    publisher: Publisher,
+    severity: Severity,
+    watches: Vec<Watch>,
+}
+
+impl Deadman {
+    pub fn new(receiver: SignalReceiver<Arc<Message>>, publisher: Publisher, config: &DeadmanConfig) -> Result<Self> {
+        let monitors = config.monitors.iter().map(|(m, s)| (Target::Monitor(m.clone()), *s));
+        let channels = config.channels.iter().map(|(c, s)| (Target::Channel(c.clone()), *s));
+        let watches = monitors
+            .chain(channels)
+            .map(|(target, secs)| Watch::new(target, Duration::from_secs(secs)))
+            .collect::<Vec<_>>();
+
+        let ticker = match watches.is_empty() {
+            true => None,
+            false => Some(ticker(Duration::from_secs(1))?),
+        };
+
+        Ok(Self {
+            receiver: ReactiveSignalReceiver::new(receiver)?,
+            ticker,
+            publisher,
+            severity: config.severity,
+            watches,
+        })
+    }
+
+    fn seen(&mut self, message: &Message) {
+        for watch in self.watches.iter_mut().filter(|w| w.matches(message)) {
+            if let Some(alert) = watch.seen(self.severity) {
+                self.publisher.publish(alert);
+            }
+        }
+    }
+
+    fn check(&mut self) {
+        for watch in self.watches.iter_mut() {
+            if let Some(alert) = watch.check(self.severity) {
+                self.publisher.publish(alert);
+            }
+        }
+    }
+}
+
+impl Reactor for Deadman {
+    type Input = ();
+    type Output = ();
+
+    fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
+        match reaction {
+            Reaction::Event(event) => {
+                if let Some(ref ticker) = self.ticker {
+                    if event.token() == ticker.token() {
+                        while let Ok(()) = ticker.try_recv() {}
+                        self.check();
+                        return Reaction::Continue;
+                    }
+                }
+
+                if event.token() == self.receiver.token() {
+                    while let Ok(message) = self.receiver.try_recv() {
+                        if !self.watches.is_empty() {
+                            self.seen(&message);
+                        }
+                    }
+                    return Reaction::Continue;
+                }
+
+                event.into()
+            }
+            Reaction::Value(()) | Reaction::Continue => Reaction::Continue,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{MessageType, Source};
+    use serde_json::Value;
+
+    fn published(channel: &str, identity: &str) -> Message {
+        let mut message = Message::new(channel, json!({}).into(), MessageType::Status, Severity::Info);
+        message.set_source(Source {
+            identity: Some(identity.into()),
+            peer: None,
+        });
+        message
+    }
+
+    fn state_of(alert: &Message) -> Value {
+        alert.lookup(&["payload".into(), "state".into()])
+    }
+
+    // Pretend nothing was seen for a while
+    fn age(watch: &mut Watch, by: Duration) {
+        watch.last_seen = Instant::now().checked_sub(by).unwrap();
+    }
+
+    #[test]
+    fn matches_monitor_or_channel() {
+        let monitor = Watch::new(Target::Monitor("web".into()), Duration::from_secs(60));
+        let channel = Watch::new(Target::Channel("cpu".into()), Duration::from_secs(60));
+
+        assert!(monitor.matches(&published("cpu", "web")));
+        assert!(monitor.matches(&published("disk", "web")));
+        assert!(!monitor.matches(&published("cpu", "db")));
+        assert!(channel.matches(&published("cpu", "db")));
+        assert!(!channel.matches(&published("disk", "web")));
+    }
+
+    #[test]
+    fn fires_once_and_rearms() {
+        let mut watch = Watch::new(Target::Monitor("web".into()), Duration::from_secs(60));
+        assert!(watch.check(Severity::Critical).is_none());
+
+        age(&mut watch, Duration::from_secs(61));
+        let alert = watch.check(Severity::Critical).unwrap();
+        assert_eq!(state_of(&alert), "firing");
+        assert_eq!(alert.severity(), Severity::Critical);
+        assert_eq!(alert.lookup(&["payload".into(), "monitor".into()]), "web");
+        assert_eq!(alert.lookup(&["payload".into(), "expected_interval".into()]), 60);
+        // Still silent, but only reported once
+        assert!(watch.check(Severity::Critical).is_none());
+
+        let alert = watch.seen(Severity::Critical).unwrap();
+        assert_eq!(state_of(&alert), "resolved");
+        assert!(watch.last_seen_at.is_some());
+        assert!(watch.seen(Severity::Critical).is_none());
+        assert!(watch.check(Severity::Critical).is_none());
+
+        age(&mut watch, Duration::from_secs(61));
+        assert_eq!(state_of(&watch.check(Severity::Critical).unwrap()), "firing");
+    }
+}
@@ -0,0 +1,39 @@
+use serde_derive::Serialize;
+use serde_json::{json, Value};
+
+use crate::messages::{Message, MessageType, Severity};
+
+mod anomaly;
+mod deadman;
+mod rules;
+mod silences;
+pub use anomaly::{Anomalies, AnomalyConfig};
+pub use deadman::{Deadman, DeadmanConfig};
+pub use rules::{RuleConfig, Rules};
+pub use silences::{NewSilence, SilenceKind, Silences, SILENCES_CHANNEL};
+
+pub const ALERTS_CHANNEL: &str = "ALERTS";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertState {
+    Firing,
+    Resolved,
+}
+
+// An alert raised by the server. Fields of `details` are added to the payload.
+pub fn alert_msg(name: &str, state: AlertState, severity: Severity, details: Value) -> Message {
+    let mut payload = json!({
+        "alert": name,
+        "state": state,
+    });
+    if let (Value::Object(payload), Value::Object(details)) = (&mut payload, details) {
+        payload.extend(details);
+    }
+
+    let (message_type, severity) = match state {
+        AlertState::Firing => (MessageType::Error, severity),
+        AlertState::Resolved => (MessageType::Status, Severity::Info),
+    };
+    Message::new(ALERTS_CHANNEL, payload.into(), message_type, severity)
+}
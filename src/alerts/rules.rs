@@ -0,0 +1,308 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::info;
+use serde_derive::Deserialize;
+use serde_json::json;
+use sonr::errors::Result;
+use sonr::reactor::{Reaction, Reactor};
+use sonr::sync::signal::{ReactiveSignalReceiver, SignalReceiver};
+
+use super::{alert_msg, AlertState, ALERTS_CHANNEL};
+use crate::filter::Filter;
+use crate::messages::{Message, Severity};
+use crate::publisher::Publisher;
+use crate::timer::ticker;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RuleConfig {
+    pub name: String,
+    // No channels means every channel
+    #[serde(default)]
+    pub channels: Vec<String>,
+    // A filter expression
+    pub condition: String,
+    // Seconds the condition has to hold before the alert fires
+    #[serde(default, rename = "for")]
+    pub hold: u64,
+    // Fire once `count` messages matched the condition within `window` seconds,
+    // instead of on every message
+    #[serde(default)]
+    pub count: Option<usize>,
+    #[serde(default)]
+    pub window: Option<u64>,
+    #[serde(default = "default_severity")]
+    pub severity: Severity,
+}
+
+fn default_severity() -> Severity {
+    Severity::Warning
+}
+
+enum Kind {
+    // Holds while the last message matched
+    Each,
+    // Holds while enough messages matched within the window
+    Count { count: usize, window: Duration },
+}
+
+// What a rule has seen on one channel
+#[derive(Default)]
+struct RuleState {
+    // Whether the last message matched
+    matched: bool,
+    // When messages matched, for counting rules
+    matched_at: VecDeque<Instant>,
+    holding_since: Option<Instant>,
+    firing: bool,
+}
+
+impl RuleState {
+    fn holds(&mut self, kind: &Kind) -> bool {
+        match *kind {
+            Kind::Each => self.matched,
+            Kind::Count { count, window } => {
+                while self.matched_at.front().is_some_and(|m| m.elapsed() > window) {
+                    self.matched_at.pop_front();
+                }
+                self.matched_at.len() >= count
+            }
+        }
+    }
+
+    // Nothing worth keeping
+    fn is_idle(&self) -> bool {
+        !self.matched && self.matched_at.is_empty() && self.holding_since.is_none() && !self.firing
+    }
+}
+
+struct Rule {
+    name: String,
+    channels: Vec<String>,
+    condition: Filter,
+    hold: Duration,
+    severity: Severity,
+    kind: Kind,
+    // Each channel fires and resolves on its own
+    states: HashMap<String, RuleState>,
+}
+
+impl Rule {
+    fn new(config: &RuleConfig) -> std::result::Result<Rule, String> {
+        let kind = match (config.count, config.window) {
+            (None, None) => Kind::Each,
+            (Some(count), Some(window)) if count > 0 && window > 0 => Kind::Count {
+                count,
+                window: Duration::from_secs(window),
+            },
+            _ => return Err("count and window must both be set and greater than 0".into()),
+        };
+
+        Ok(Rule {
+            name: config.name.clone(),
+            channels: config.channels.clone(),
+            condition: Filter::parse(&config.condition)?,
+            hold: Duration::from_secs(config.hold),
+            severity: config.severity,
+            kind,
+            states: HashMap::new(),
+        })
+    }
+
+    fn applies_to(&self, message: &Message) -> bool {
+        self.channels.is_empty() || self.channels.iter().any(|c| c == message.channel())
+    }
+
+    fn evaluate(&mut self, message: &Message) -> Option<Message> {
+        let matched = self.condition.matches(message);
+        let channel = message.channel();
+        if !matched && !self.states.contains_key(channel) {
+            return None;
+        }
+
+        let state = self.states.entry(channel.to_owned()).or_default();
+        state.matched = matched;
+        if matched {
+            if let Kind::Count { .. } = self.kind {
+                state.matched_at.push_back(Instant::now());
+            }
+        }
+        self.update(channel)
+    }
+
+    // Rules can start firing after their hold time, or stop once
+    // matches fall out of the window, without any new messages.
+    fn tick(&mut self) -> Vec<Message> {
+        let channels: Vec<String> = self.states.keys().cloned().collect();
+        channels.iter().filter_map(|channel| self.update(channel)).collect()
+    }
+
+    fn update(&mut self, channel: &str) -> Option<Message> {
+        let state = self.states.get_mut(channel)?;
+        let state_changed = if !state.holds(&self.kind) {
+            state.holding_since = None;
+            match state.firing {
+                true => {
+                    state.firing = false;
+                    Some(AlertState::Resolved)
+                }
+                false => None,
+            }
+        } else {
+            let since = *state.holding_since.get_or_insert_with(Instant::now);
+            if !state.firing && since.elapsed() >= self.hold {
+                state.firing = true;
+                info!("Alert \"{}\" firing on {}", self.name, channel);
+                Some(AlertState::Firing)
+            } else {
+                None
+            }
+        };
+
+        let count = state.matched_at.len();
+        if state.is_idle() {
+            self.states.remove(channel);
+        }
+        state_changed.map(|alert_state| self.alert(channel, alert_state, count))
+    }
+
+    fn alert(&self, channel: &str, state: AlertState, count: usize) -> Message {
+        let mut details = json!({ "channel": channel });
+        if let Kind::Count { .. } = self.kind {
+            details["count"] = json!(count);
+        }
+        alert_msg(&self.name, state, self.severity, details)
+    }
+}
+
+// Evaluates the configured rules against every published message and
+// publishes alerts as they start and stop firing.
+pub struct Rules {
+    receiver: ReactiveSignalReceiver<Arc<Message>>,
+    ticker: Option<ReactiveSignalReceiver<()>>,
+    publisher: Publisher,
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    pub fn new(receiver: SignalReceiver<Arc<Message>>, publisher: Publisher, configs: &[RuleConfig]) -> Result<Self> {
+        let mut rules = Vec::new();
+        for config in configs {
+            let rule = Rule::new(config).map_err(|reason| {
+                let reason = format!("invalid rule \"{}\": {}", config.name, reason);
+                io::Error::new(io::ErrorKind::InvalidInput, reason)
+            })?;
+            rules.push(rule);
+        }
+
+        let ticker = match rules.is_empty() {
+            true => None,
+            false => Some(ticker(Duration::from_secs(1))?),
+        };
+
+        Ok(Self {
+            receiver: ReactiveSignalReceiver::new(receiver)?,
+            ticker,
+            publisher,
+            rules,
+        })
+    }
+
+    fn evaluate(&mut self, message: &Message) {
+        // Never alert on alerts
+        if message.channel() == ALERTS_CHANNEL {
+            return;
+        }
+
+        for rule in self.rules.iter_mut().filter(|r| r.applies_to(message)) {
+            if let Some(alert) = rule.evaluate(message) {
+                self.publisher.publish(alert);
+            }
+        }
+    }
+
+    fn tick(&mut self) {
+        for rule in self.rules.iter_mut() {
+            for alert in rule.tick() {
+                self.publisher.publish(alert);
+            }
+        }
+    }
+}
+
+impl Reactor for Rules {
+    type Input = ();
+    type Output = ();
+
+    fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
+        match reaction {
+            Reaction::Event(event) => {
+                if let Some(ref ticker) = self.ticker {
+                    if event.token() == ticker.token() {
+                        while let Ok(()) = ticker.try_recv() {}
+                        self.tick();
+                        return Reaction::Continue;
+                    }
+                }
+
+                if event.token() == self.receiver.token() {
+                    while let Ok(message) = self.receiver.try_recv() {
+                        if !self.rules.is_empty() {
+                            self.evaluate(&message);
+                        }
+                    }
+                    return Reaction::Continue;
+                }
+
+                event.into()
+            }
+            Reaction::Value(()) | Reaction::Continue => Reaction::Continue,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::MessageType;
+    use serde_json::Value;
+
+    fn status(channel: &str, cpu: u64) -> Message {
+        Message::new(channel, json!({ "cpu": cpu }).into(), MessageType::Status, Severity::Info)
+    }
+
+    fn state_of(alert: &Message) -> (Value, Value) {
+        let lookup = |field: &str| alert.lookup(&["payload".into(), field.into()]);
+        (lookup("state"), lookup("channel"))
+    }
+
+    #[test]
+    fn fires_and_resolves_per_channel() {
+        let config = RuleConfig {
+            name: "high_cpu".into(),
+            channels: Vec::new(),
+            condition: "payload.cpu > 90".into(),
+            hold: 0,
+            count: None,
+            window: None,
+            severity: Severity::Warning,
+        };
+        let mut rule = Rule::new(&config).unwrap();
+
+        let alert = rule.evaluate(&status("web1", 95)).unwrap();
+        assert_eq!(state_of(&alert), (json!("firing"), json!("web1")));
+        let alert = rule.evaluate(&status("web2", 99)).unwrap();
+        assert_eq!(state_of(&alert), (json!("firing"), json!("web2")));
+
+        // Another channel doesn't resolve web1
+        assert!(rule.evaluate(&status("web3", 10)).is_none());
+        assert!(rule.evaluate(&status("web2", 97)).is_none());
+
+        let alert = rule.evaluate(&status("web1", 20)).unwrap();
+        assert_eq!(state_of(&alert), (json!("resolved"), json!("web1")));
+        assert!(rule.tick().is_empty());
+        assert_eq!(rule.states.len(), 1);
+    }
+}
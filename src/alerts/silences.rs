@@ -0,0 +1,332 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use log::error;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::ALERTS_CHANNEL;
+use crate::filter::glob;
+use crate::messages::{Acknowledged, Message, MessageType, Severity, Source};
+use crate::timer::now_millis;
+
+pub const SILENCES_CHANNEL: &str = "SILENCES";
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SilenceKind {
+    // Matching messages are not delivered to clients or sinks
+    Silence,
+    // Matching messages are published with `acknowledged` set
+    Acknowledge,
+}
+
+// A silence or acknowledgement as requested by a client
+#[derive(Debug, Deserialize)]
+pub struct NewSilence {
+    // `*` matches any number of characters
+    channels: String,
+    // Only alerts with this name
+    #[serde(default)]
+    alert: Option<String>,
+    // Milliseconds since the unix epoch, now if left out
+    #[serde(default)]
+    starts_at: Option<u64>,
+    #[serde(default)]
+    ends_at: Option<u64>,
+    // Seconds from `starts_at`, instead of `ends_at`
+    #[serde(default)]
+    duration: Option<u64>,
+    #[serde(default)]
+    comment: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Silence {
+    id: String,
+    kind: SilenceKind,
+    channels: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alert: Option<String>,
+    starts_at: u64,
+    // Without an end a silence lasts until removed, and an acknowledgement
+    // of an alert until the alert is resolved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ends_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    by: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+}
+
+impl Silence {
+    // Reply to a client adding a silence
+    pub fn added_msg(&self) -> Message {
+        let payload = json!({ "silence": self });
+        Message::new(SILENCES_CHANNEL, payload.into(), MessageType::System, Severity::Info)
+    }
+
+    // A silence on a channel also covers alerts about that channel
+    fn matches(&self, message: &Message) -> bool {
+        let is_alert = message.channel() == ALERTS_CHANNEL;
+        if let Some(ref alert) = self.alert {
+            if !is_alert || message.lookup(&["payload".into(), "alert".into()]) != Value::String(alert.clone()) {
+                return false;
+            }
+        }
+
+        if glob(&self.channels, message.channel()) {
+            return true;
+        }
+        match message.lookup(&["payload".into(), "channel".into()]) {
+            Value::String(ref channel) if is_alert => glob(&self.channels, channel),
+            _ => false,
+        }
+    }
+
+    fn is_active(&self, now: u64) -> bool {
+        self.starts_at <= now
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        self.ends_at.is_some_and(|ends_at| ends_at <= now)
+    }
+}
+
+#[derive(Default)]
+struct State {
+    silences: Vec<Silence>,
+    next_id: u64,
+    // Bumped on every change
+    version: u64,
+}
+
+// Silences and acknowledgements, shared between all threads and saved to
+// disk when there is a data directory. Changes made by clients are saved
+// straight away, silences expiring or being resolved when `save` is next
+// called.
+#[derive(Clone)]
+pub struct Silences {
+    state: Arc<Mutex<State>>,
+    // The version on disk, locked while saving so only newer versions are written
+    saved: Arc<Mutex<u64>>,
+    path: Option<Arc<PathBuf>>,
+    epoch: u64,
+}
+
+impl Silences {
+    pub fn new(path: Option<PathBuf>) -> io::Result<Self> {
+        let silences = match path {
+            Some(ref path) => match fs::read_to_string(path) {
+                Ok(s) => serde_json::from_str(&s).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?,
+                Err(ref e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+                Err(e) => return Err(e),
+            },
+            None => Vec::new(),
+        };
+
+        let state = State {
+            silences,
+            next_id: 0,
+            version: 0,
+        };
+
+        Ok(Self {
+            state: Arc::new(Mutex::new(state)),
+            saved: Arc::new(Mutex::new(0)),
+            path: path.map(Arc::new),
+            epoch: now_millis(),
+        })
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    // Write the silences to disk if they changed, without holding up
+    // anyone publishing meanwhile
+    pub fn save(&self) {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return,
+        };
+
+        let mut saved = match self.saved.lock() {
+            Ok(saved) => saved,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let (version, data) = {
+            let state = self.state();
+            if state.version == *saved {
+                return;
+            }
+            (state.version, serde_json::to_vec(&state.silences))
+        };
+
+        let tmp = path.with_extension("tmp");
+        let result = data
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+            .and_then(|data| fs::write(&tmp, data))
+            .and_then(|_| fs::rename(&tmp, &**path));
+        match result {
+            Ok(()) => *saved = version,
+            Err(e) => error!("Failed to save silences: {:?}", e),
+        }
+    }
+
+    pub fn add(&self, kind: SilenceKind, new: NewSilence, source: &Source) -> Result<Silence, String> {
+        let starts_at = new.starts_at.unwrap_or_else(now_millis);
+        let ends_at = match (new.ends_at, new.duration) {
+            (Some(_), Some(_)) => return Err("Only one of ends_at and duration can be set".into()),
+            (Some(ends_at), None) => Some(ends_at),
+            (None, Some(duration)) => {
+                let ends_at = duration.checked_mul(1000).and_then(|ms| starts_at.checked_add(ms));
+                Some(ends_at.ok_or("Duration is too large")?)
+            }
+            (None, None) => None,
+        };
+        if ends_at.is_some_and(|ends_at| ends_at <= starts_at) {
+            return Err("A silence has to end after it starts".into());
+        }
+        let mut state = self.state();
+        state.next_id += 1;
+        state.version += 1;
+        let silence = Silence {
+            id: format!("{:x}-{:x}", self.epoch, state.next_id),
+            kind,
+            channels: new.channels,
+            alert: new.alert,
+            starts_at,
+            ends_at,
+            by: source.identity.clone(),
+            comment: new.comment,
+        };
+        state.silences.push(silence.clone());
+        drop(state);
+
+        self.save();
+        Ok(silence)
+    }
+
+    pub fn remove(&self, id: &str) -> Result<(), String> {
+        let mut state = self.state();
+        let count = state.silences.len();
+        state.silences.retain(|s| s.id != id);
+        if state.silences.len() == count {
+            return Err(format!("No silence with id \"{}\"", id));
+        }
+        state.version += 1;
+        drop(state);
+
+        self.save();
+        Ok(())
+    }
+
+    // Reply to a client asking for the current silences
+    pub fn list_msg(&self) -> Message {
+        let payload = json!({ "silences": self.state().silences });
+        Message::new(SILENCES_CHANNEL, payload.into(), MessageType::System, Severity::Info)
+    }
+
+    // Annotate a message about to be published if it has been acknowledged
+    pub fn annotate(&self, message: &mut Message) {
+        let now = now_millis();
+        let mut state = self.state();
+        let mut changed = false;
+
+        let count = state.silences.len();
+        state.silences.retain(|s| !s.is_expired(now));
+        changed |= state.silences.len() != count;
+
+        let acknowledged = state
+            .silences
+            .iter()
+            .rfind(|s| s.kind == SilenceKind::Acknowledge && s.is_active(now) && s.matches(message))
+            .map(|silence| Acknowledged {
+                id: silence.id.clone(),
+                by: silence.by.clone(),
+                comment: silence.comment.clone(),
+            });
+
+        // Acknowledgements of an alert without an end are done once it resolves
+        let resolved = message.channel() == ALERTS_CHANNEL
+            && message.lookup(&["payload".into(), "state".into()]) == Value::String("resolved".into());
+        if resolved {
+            let count = state.silences.len();
+            state.silences.retain(|s| {
+                !(s.kind == SilenceKind::Acknowledge && s.alert.is_some() && s.ends_at.is_none() && s.matches(message))
+            });
+            changed |= state.silences.len() != count;
+        }
+
+        if changed {
+            state.version += 1;
+        }
+        message.acknowledge(acknowledged);
+    }
+
+    // Whether a message is kept from clients and sinks. Everything is still
+    // published, so rules, aggregates and the like see silenced channels too.
+    pub fn is_silenced(&self, message: &Message) -> bool {
+        let now = now_millis();
+        self.state().silences.iter().any(|s| {
+            s.kind == SilenceKind::Silence && s.is_active(now) && !s.is_expired(now) && s.matches(message)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_silence(value: Value) -> NewSilence {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn rejects_durations_that_overflow() {
+        let silences = Silences::new(None).unwrap();
+        let source = Source::default();
+
+        let new = new_silence(json!({ "channels": "web*", "starts_at": 1000, "duration": u64::MAX }));
+        assert!(silences.add(SilenceKind::Silence, new, &source).is_err());
+        let new = new_silence(json!({ "channels": "web*", "starts_at": u64::MAX - 1000, "duration": 2 }));
+        assert!(silences.add(SilenceKind::Silence, new, &source).is_err());
+
+        let new = new_silence(json!({ "channels": "web*", "starts_at": 1000, "duration": 60 }));
+        let silence = silences.add(SilenceKind::Silence, new, &source).unwrap();
+        assert_eq!(silence.ends_at, Some(61_000));
+    }
+
+    #[test]
+    fn silences_and_acknowledges_alerts_about_channels() {
+        let silences = Silences::new(None).unwrap();
+        let source = Source::default();
+        let new = new_silence(json!({ "channels": "web*" }));
+        silences.add(SilenceKind::Silence, new, &source).unwrap();
+        let new = new_silence(json!({ "channels": "db1", "alert": "high_cpu" }));
+        silences.add(SilenceKind::Acknowledge, new, &source).unwrap();
+
+        let alert = |channel: &str, state: &str| {
+            let payload = json!({ "alert": "high_cpu", "state": state, "channel": channel });
+            Message::new(ALERTS_CHANNEL, payload.into(), MessageType::Error, Severity::Critical)
+        };
+        assert!(silences.is_silenced(&alert("web1", "firing")));
+        assert!(!silences.is_silenced(&alert("db1", "firing")));
+
+        let mut firing = alert("db1", "firing");
+        silences.annotate(&mut firing);
+        assert!(firing.lookup(&["acknowledged".into()]).is_object());
+
+        // Resolving the alert ends the acknowledgement
+        silences.annotate(&mut alert("db1", "resolved"));
+        let mut firing = alert("db1", "firing");
+        silences.annotate(&mut firing);
+        assert!(firing.lookup(&["acknowledged".into()]).is_null());
+    }
+}
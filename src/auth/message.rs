@@ -1,7 +1,23 @@
 use serde::Deserialize;
 use bytes::Bytes;
 
+use crate::compression::Compression;
+use crate::messages::Metadata;
+
 #[derive(Debug, Deserialize)]
 pub struct AuthMessage {
     pub payload: Bytes,
+    #[serde(default)]
+    pub compression: Option<Vec<Compression>>,
+    // What a monitor tells about itself, as in a register message, so its
+    // first messages already carry its tags
+    #[serde(default)]
+    pub metadata: Option<Metadata>,
+}
+
+// The first line on a unix domain socket when `uds_compression` is set
+#[derive(Debug, Deserialize)]
+pub struct Handshake {
+    #[serde(default)]
+    pub compression: Option<Vec<Compression>>,
 }
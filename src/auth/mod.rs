@@ -1,4 +1,4 @@
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 use std::marker::PhantomData;
 use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
@@ -11,20 +11,46 @@ use sonr::prelude::*;
 use sonr::Evented;
 use sonr::net::stream::StreamRef;
 use sonr::reactor::{Reaction, Reactor};
-use sonr::sync::signal::SignalSender;
+use sonr::sync::signal::{ReactiveSignalReceiver, SignalSender};
 
+use crate::compression::{Compressed, Compression};
 use crate::config::Config;
+use crate::messages::{status_msg, Metadata, Source};
 use crate::throttle::{Throttle, ThrottleKey};
+use crate::timer::ticker;
 use sonr_connection::{Codec, Connection};
 
 mod message;
-pub use message::AuthMessage;
+mod negotiation;
+mod permission;
+pub use message::{AuthMessage, Handshake};
+pub use negotiation::Negotiation;
+pub use permission::{Permission, Permissions};
+
+// A connection that has been through authentication (or didn't need it)
+// along with who is on the other end.
+pub struct Session<T> {
+    pub stream: T,
+    pub source: Source,
+    // Sent by monitors while authenticating
+    pub metadata: Option<Metadata>,
+}
+
+impl<T> Session<T> {
+    pub fn unauthenticated(stream: T) -> Self {
+        Self {
+            stream,
+            source: Source::default(),
+            metadata: None,
+        }
+    }
+}
 
 #[derive(Debug)]
 enum AuthState {
     NotAuthenticated,
     ClientId(Vec<u8>),
-    Authenticated,
+    Authenticated(String),
     Throttled(Instant, Duration),
 }
 
@@ -42,7 +68,7 @@ impl AuthState {
                     .get(&id)
                     .map(|secret| secret.as_bytes() == data.as_ref())
                 {
-                    Some(true) => Authenticated,
+                    Some(true) => Authenticated(id),
                     _ => Throttled(Instant::now(), Duration::from_secs(THROTTLE_TIME_SEC)),
                 }
             }
@@ -50,7 +76,7 @@ impl AuthState {
                 true => ClientId(data.to_vec()),
                 false => Throttled(*instant, *duration),
             },
-            Authenticated => {
+            Authenticated(_) => {
                 error!("Client was already authenticated");
                 panic!("----> this should not happen")
             }
@@ -60,15 +86,19 @@ impl AuthState {
     }
 }
 
+// A connection waiting to authenticate, with when it connected
+type Authenticating<T, C> = (Connection<T, C>, AuthState, Option<Vec<Compression>>, Option<Metadata>, Instant);
+
 pub struct Authentication<T, C, S>
 where
     T: StreamRef<Evented=S> + Read + Write,
     C: Codec<Message = AuthMessage>,
     S: Evented + Read + Write + ThrottleKey,
 {
-    connections: HashMap<Token, (Connection<T, C>, AuthState)>,
+    connections: HashMap<Token, Authenticating<T, C>>,
     config: Arc<Config>,
     throttle_tx: Option<SignalSender<(String, Throttle)>>,
+    deadline: Option<(Duration, ReactiveSignalReceiver<()>)>,
     _p: PhantomData<S>,
 }
 
@@ -78,16 +108,57 @@ where
     C: Codec<Message = AuthMessage>,
     S: Evented + Read + Write + ThrottleKey,
 {
-    pub fn new(config: Arc<Config>, throttle_tx: Option<SignalSender<(String, Throttle)>>) -> Self {
-        Self {
+    pub fn new(config: Arc<Config>, throttle_tx: Option<SignalSender<(String, Throttle)>>) -> SonrResult<Self> {
+        let deadline = match config.timeouts.auth {
+            Some(secs) => Some((Duration::from_secs(secs), ticker(Duration::from_secs(1))?)),
+            None => None,
+        };
+
+        Ok(Self {
             connections: HashMap::new(),
             config,
             throttle_tx,
+            deadline,
             _p: PhantomData,
+        })
+    }
+
+    // Drop connections that didn't authenticate in time
+    fn check_deadline(&mut self, timeout: Duration) {
+        let expired = self
+            .connections
+            .iter()
+            .filter(|(_, (_, _, _, _, connected))| connected.elapsed() > timeout)
+            .map(|(token, _)| *token)
+            .collect::<Vec<_>>();
+
+        for token in expired {
+            info!("Authentication timed out");
+            self.connections.remove(&token);
         }
     }
 }
 
+// Wrap the stream in the compression negotiated with the peer, during
+// authentication or the unix domain socket handshake. Peers that asked for
+// compression are told the outcome in an uncompressed status message,
+// everything after that is compressed.
+fn compressed<T, C>(stream: T, requested: Option<Vec<Compression>>, allowed: &[Compression]) -> io::Result<Compressed<T>>
+where
+    T: Read + Write,
+    C: Codec,
+{
+    let requested = match requested {
+        Some(requested) => requested,
+        None => return Compressed::new(stream, Compression::None),
+    };
+
+    let compression = Compression::negotiate(&requested, allowed);
+    let mut stream = Compressed::new(stream, compression)?;
+    stream.preamble(C::encode(status_msg(compression.as_str())));
+    Ok(stream)
+}
+
 impl<T, C, S> Reactor for Authentication<T, C, S>
 where
     T: StreamRef<Evented=S> + Read + Write,
@@ -95,7 +166,7 @@ where
     S: Evented + Read + Write + ThrottleKey,
 {
     type Input = T;
-    type Output = T;
+    type Output = Session<Compressed<T>>;
 
     fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
         match reaction {
@@ -104,32 +175,44 @@ where
                 let connection = Connection::new(stream, codec);
                 self.connections.insert(
                     connection.token(),
-                    (connection, AuthState::NotAuthenticated),
+                    (connection, AuthState::NotAuthenticated, None, None, Instant::now()),
                 );
                 Reaction::Continue
             }
 
             Reaction::Event(event) => {
-                if let Some((connection, state)) = self.connections.get_mut(&event.token()) {
+                if let Some((timeout, ref ticker)) = self.deadline {
+                    if event.token() == ticker.token() {
+                        while let Ok(()) = ticker.try_recv() {}
+                        self.check_deadline(timeout);
+                        return Reaction::Continue;
+                    }
+                }
+
+                if let Some((connection, state, compression, metadata, _)) = self.connections.get_mut(&event.token()) {
                     let config = self.config.clone();
                     let mut vals = VecDeque::new();
                     let reacto = connection.react(event.into());
-                    match reacto {
-                        Reaction::Value(val) => {
+                    if let Reaction::Value(val) = reacto {
+                        vals.push_back(val);
+                        while let Reaction::Value(val) = connection.react(Reaction::Continue) {
                             vals.push_back(val);
-                            while let Reaction::Value(val) = connection.react(Reaction::Continue) {
-                                vals.push_back(val);
-                            }
                         }
-                        _ => {}
                     }
 
                     for val in vals {
                         match val {
-                            Ok(msg) => match state.authenticate(msg.payload, &config) {
-                                Ok(new_state) => *state = new_state,
-                                Err(_) => {}
-                            },
+                            Ok(msg) => {
+                                if msg.compression.is_some() {
+                                    *compression = msg.compression;
+                                }
+                                if msg.metadata.is_some() {
+                                    *metadata = msg.metadata;
+                                }
+                                if let Ok(new_state) = state.authenticate(msg.payload, &config) {
+                                    *state = new_state;
+                                }
+                            }
                             Err(e) => {
                                 dbg!(e);
                                 self.connections.remove(&event.token());
@@ -142,17 +225,26 @@ where
                                 if let Ok(key) = connection.stream_ref().inner().get_throttle_key() {
                                     info!("Throttled  {:?}", connection.stream_ref().inner().get_throttle_key());
                                     if let Some(tx) =  &self.throttle_tx {
-                                        let _ = tx.send((key, Throttle::new(i.clone(), d.clone())));
+                                        let _ = tx.send((key, Throttle::new(*i, *d)));
                                     }
                                 }
                                 self.connections.remove(&event.token());
                                 return Reaction::Continue
                             }
-                            AuthState::Authenticated => {
+                            AuthState::Authenticated(identity) => {
+                                let identity = identity.clone();
                                 match self.connections.remove(&event.token()) {
-                                    Some((connection, _state)) => {
-                                        info!("Authenticated {:?}", connection.stream_ref().inner().get_throttle_key());
-                                        return Reaction::Value(connection.into_inner());
+                                    Some((connection, _state, requested, metadata, _)) => {
+                                        let peer = connection.stream_ref().inner().get_throttle_key().ok();
+                                        info!("Authenticated {} {:?}", identity, peer);
+                                        let source = Source { identity: Some(identity), peer };
+                                        return match compressed::<_, C>(connection.into_inner(), requested, &config.compression) {
+                                            Ok(stream) => Reaction::Value(Session { stream, source, metadata }),
+                                            Err(e) => {
+                                                error!("{:?}", e);
+                                                Reaction::Continue
+                                            }
+                                        }
                                     }
                                     None => return Reaction::Continue
                                 }
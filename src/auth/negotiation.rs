@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use log::error;
+use sonr::net::stream::StreamRef;
+use sonr::prelude::*;
+use sonr::reactor::{Reaction, Reactor};
+use sonr_connection::{Codec, Connection};
+
+use super::{compressed, Handshake, Session};
+use crate::compression::{Compressed, Compression};
+use crate::config::Config;
+use crate::messages::Source;
+
+// Unix domain socket connections skip authentication, so when
+// `uds_compression` is set they start with a handshake naming the
+// compression they accept instead. Otherwise they are passed on uncompressed.
+pub struct Negotiation<T, C>
+where
+    T: StreamRef + Read + Write,
+    C: Codec<Message = Handshake>,
+{
+    connections: HashMap<Token, (Connection<T, C>, Source)>,
+    config: Arc<Config>,
+    _p: PhantomData<C>,
+}
+
+impl<T, C> Negotiation<T, C>
+where
+    T: StreamRef + Read + Write,
+    C: Codec<Message = Handshake>,
+{
+    pub fn new(config: Arc<Config>) -> Self {
+        Self {
+            connections: HashMap::new(),
+            config,
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<T, C> Reactor for Negotiation<T, C>
+where
+    T: StreamRef + Read + Write,
+    C: Codec<Message = Handshake>,
+{
+    type Input = Session<T>;
+    type Output = Session<Compressed<T>>;
+
+    fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
+        match reaction {
+            Reaction::Value(session) => {
+                if !self.config.uds_compression {
+                    return match Compressed::new(session.stream, Compression::None) {
+                        Ok(stream) => Reaction::Value(Session { stream, source: session.source, metadata: session.metadata }),
+                        Err(e) => {
+                            error!("{:?}", e);
+                            Reaction::Continue
+                        }
+                    };
+                }
+                let connection = Connection::new(session.stream, C::default());
+                self.connections.insert(connection.token(), (connection, session.source));
+                Reaction::Continue
+            }
+
+            Reaction::Event(event) => {
+                let handshake = match self.connections.get_mut(&event.token()) {
+                    Some((connection, _)) => match connection.react(event.into()) {
+                        Reaction::Value(handshake) => handshake,
+                        Reaction::Continue => return Reaction::Continue,
+                        Reaction::Event(_) => return Reaction::Continue,
+                    },
+                    None => return event.into(),
+                };
+
+                let (connection, source) = match self.connections.remove(&event.token()) {
+                    Some(entry) => entry,
+                    None => return Reaction::Continue,
+                };
+                let handshake = match handshake {
+                    Ok(handshake) => handshake,
+                    Err(e) => {
+                        error!("Invalid handshake: {:?}", e);
+                        return Reaction::Continue;
+                    }
+                };
+
+                match compressed::<_, C>(connection.into_inner(), handshake.compression, &self.config.compression) {
+                    Ok(stream) => Reaction::Value(Session { stream, source, metadata: None }),
+                    Err(e) => {
+                        error!("{:?}", e);
+                        Reaction::Continue
+                    }
+                }
+            }
+
+            Reaction::Continue => Reaction::Continue,
+        }
+    }
+}
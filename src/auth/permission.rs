@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+
+use serde_derive::Deserialize;
+
+use crate::messages::Source;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    // Acknowledge alerts and silence channels
+    Silence,
+    // Send requests to monitors
+    Request,
+    // Publish messages
+    Publish,
+    // Consume durable subscriptions
+    Consume,
+}
+
+// What each identity is allowed to do on top of receiving messages.
+// Connections without an identity (unix domain sockets) have no permissions.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct Permissions {
+    identities: HashMap<String, Vec<Permission>>,
+}
+
+impl Permissions {
+    pub fn allows(&self, source: &Source, permission: Permission) -> bool {
+        source
+            .identity
+            .as_ref()
+            .and_then(|identity| self.identities.get(identity))
+            .is_some_and(|permissions| permissions.contains(&permission))
+    }
+
+    pub fn check(&self, source: &Source, permission: Permission) -> Result<(), String> {
+        match self.allows(source, permission) {
+            true => Ok(()),
+            false => Err("Permission denied".into()),
+        }
+    }
+}
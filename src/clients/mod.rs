@@ -0,0 +1,468 @@
+use std::io::{Read, Write};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::error;
+use sonr::errors::Result;
+use sonr::reactor::{Reaction, Reactor};
+use sonr::sync::signal::{ReactiveSignalReceiver, SignalReceiver};
+use sonr::sync::Capacity;
+use sonr::net::stream::StreamRef;
+use sonr::Token;
+
+use sonr_connection::Codec;
+use crate::alerts::{SilenceKind, Silences};
+use crate::auth::{Permission, Session};
+use crate::config::Config;
+use crate::durable::{Delivery, Queues};
+use crate::history::{position, History};
+use crate::messages::{error_msg, ping_msg, pong_msg, status_msg, Message, MessageType, Selector, Source};
+use crate::presence::Presence;
+use crate::publisher::Publisher;
+use crate::ratelimit::{ExcessPolicy, RateLimiter};
+use crate::router::Router;
+use crate::rollups::{Queries, QueryReply};
+use crate::timer::{ticker, Idle, IdleTimeout, Liveness};
+
+mod outbox;
+mod pending;
+mod request;
+mod sessions;
+mod subscription;
+pub use outbox::{Outbox, Overflow, QueueConfig};
+use pending::PendingRequests;
+pub use request::Request;
+use sessions::{resumed_msg, started_msg};
+pub use sessions::{SessionConfig, Sessions, SESSION_CHANNEL};
+pub use subscription::Subscriptions;
+
+struct Client<T, C>
+where
+    T: StreamRef + Read + Write,
+    C: Codec,
+{
+    outbox: Outbox<T, C>,
+    subscriptions: Subscriptions,
+    liveness: Liveness,
+    source: Source,
+    // The session token, if one is started or resumed
+    session: Option<String>,
+    // Messages up to this position were replayed when resuming
+    replayed_through: Option<u64>,
+    // Durable subscriptions being consumed, with the consumer id
+    consuming: Vec<(String, u64)>,
+}
+
+// What clients on every thread share
+#[derive(Clone)]
+pub struct Context {
+    pub publisher: Publisher,
+    pub rate_limiter: RateLimiter,
+    pub presence: Presence,
+    pub silences: Silences,
+    pub queries: Option<Queries>,
+    pub router: Router,
+    pub history: History,
+    pub sessions: Sessions,
+    pub queues: Queues,
+    pub config: Arc<Config>,
+}
+
+impl<T, C> Client<T, C>
+where
+    T: StreamRef + Read + Write,
+    C: Codec,
+{
+    fn handle(
+        &mut self,
+        request: Request,
+        context: &Context,
+        pending: &mut PendingRequests,
+        deliveries: &ReactiveSignalReceiver<Delivery>,
+        query_replies: &ReactiveSignalReceiver<QueryReply>,
+    ) -> std::result::Result<(), String> {
+        let permissions = &context.config.permissions;
+        match request {
+            Request::Subscribe(subscription) => self.subscriptions.subscribe(subscription)?,
+            Request::Unsubscribe { id } => self.subscriptions.unsubscribe(id),
+            Request::Monitors { tags } => {
+                let selector = match tags {
+                    Some(tags) => Some(Selector::parse(&tags)?),
+                    None => None,
+                };
+                self.outbox.send(C::encode(context.presence.online_msg(selector.as_ref())));
+            }
+            Request::Silence(new) => {
+                permissions.check(&self.source, Permission::Silence)?;
+                let silence = context.silences.add(SilenceKind::Silence, new, &self.source)?;
+                self.outbox.send(C::encode(silence.added_msg()));
+            }
+            Request::Acknowledge(new) => {
+                permissions.check(&self.source, Permission::Silence)?;
+                let silence = context.silences.add(SilenceKind::Acknowledge, new, &self.source)?;
+                self.outbox.send(C::encode(silence.added_msg()));
+            }
+            Request::Unsilence { id } => {
+                permissions.check(&self.source, Permission::Silence)?;
+                context.silences.remove(&id)?;
+                self.outbox.send(C::encode(status_msg("OK")));
+            }
+            Request::Silences => self.outbox.send(C::encode(context.silences.list_msg())),
+            Request::Query(query) => {
+                let queries = context.queries.as_ref().ok_or("Rollups are not enabled")?;
+                let token = self.outbox.connection().token();
+                queries.run(query, token, query_replies.sender())?;
+            }
+            Request::Ask(request) => {
+                permissions.check(&self.source, Permission::Request)?;
+                let token = self.outbox.connection().token();
+                pending.send(&context.router, token, request, self.source.clone())?;
+            }
+            Request::Session => {
+                let token = context.sessions.start(&self.source)?;
+                if let Some(old) = self.session.replace(token.clone()) {
+                    context.sessions.end(&old);
+                }
+                self.outbox.send(C::encode(started_msg(&token)));
+            }
+            Request::Resume { session, last_id, sequence } => self.resume(session, last_id, sequence, context)?,
+            Request::Consume { name } => {
+                permissions.check(&self.source, Permission::Consume)?;
+                // Consuming again starts over, even if another connection took over meanwhile
+                if let Some(index) = self.consuming.iter().position(|(n, _)| *n == name) {
+                    let (name, consumer) = self.consuming.remove(index);
+                    context.queues.release(&name, consumer);
+                }
+                let token = self.outbox.connection().token();
+                let consumer = context.queues.consume(&name, token, deliveries.sender())?;
+                self.consuming.push((name, consumer));
+                self.outbox.send(C::encode(status_msg("OK")));
+            }
+            Request::Ack { id } => {
+                if self.consuming.is_empty() {
+                    return Err("Not consuming a durable subscription".into());
+                }
+                for (name, consumer) in &self.consuming {
+                    context.queues.ack(name, *consumer, &id);
+                }
+            }
+            Request::Publish { mut message } => {
+                permissions.check(&self.source, Permission::Publish)?;
+                match message.message_type() {
+                    MessageType::Ping | MessageType::Pong | MessageType::Request | MessageType::Reply | MessageType::Register => {
+                        return Err("Only monitors can send this message type".into());
+                    }
+                    _ => {}
+                }
+                message.validate()?;
+                if let Err(excess) = context.rate_limiter.check(&self.source, None, message.channel()) {
+                    if let Some(report) = excess.report_msg() {
+                        context.publisher.publish(report);
+                    }
+                    return match (excess.policy, excess.reason()) {
+                        (ExcessPolicy::Drop, _) | (_, None) => Ok(()),
+                        (_, Some(reason)) => Err(reason),
+                    };
+                }
+                message.set_source(self.source.clone());
+                context.publisher.publish(message);
+            }
+            Request::Ping => self.outbox.send(C::encode(pong_msg())),
+            Request::Pong => {}
+        }
+        Ok(())
+    }
+
+    fn resume(
+        &mut self,
+        session: String,
+        last_id: Option<String>,
+        sequence: Option<HashMap<String, u64>>,
+        context: &Context,
+    ) -> std::result::Result<(), String> {
+        if self.session.is_some() {
+            return Err("A session is already started".into());
+        }
+        if last_id.is_some() && sequence.is_some() {
+            return Err("Resume from either last_id or sequence, not both".into());
+        }
+        // Before the session, which is unknown after a restart as well, so
+        // the client is told the server has restarted
+        let missed = match (last_id, sequence) {
+            (Some(id), _) => Some(context.history.since(&id)?),
+            (None, Some(sequence)) => Some(context.history.since_sequences(&sequence)?),
+            (None, None) => None,
+        };
+        let subscriptions = context.sessions.resume(&session, &self.source)?;
+        self.subscriptions = subscriptions;
+        self.session = Some(session.clone());
+
+        // Queued behind the missed messages, so live delivery follows them
+        let (replayed, complete) = match missed {
+            Some(missed) => {
+                self.replayed_through = Some(missed.through);
+                let mut replayed = 0;
+                for message in missed.messages {
+                    if !self.subscriptions.accepts(&message) || context.silences.is_silenced(&message) {
+                        continue;
+                    }
+                    if let Err(Overflow::Disconnect) = self.outbox.push(message.channel(), C::encode(&*message)) {
+                        return Err("Too many messages queued".into());
+                    }
+                    replayed += 1;
+                }
+                (replayed, missed.complete)
+            }
+            None => (0, true),
+        };
+        let resumed = C::encode(resumed_msg(&session, replayed, complete));
+        if let Err(Overflow::Disconnect) = self.outbox.push(SESSION_CHANNEL, resumed) {
+            return Err("Too many messages queued".into());
+        }
+        Ok(())
+    }
+
+    // Live messages, consumers only receive what they subscribe to
+    fn wants(&mut self, message: &Message) -> bool {
+        if !self.consuming.is_empty() && self.subscriptions.is_empty() {
+            return false;
+        }
+        !self.is_replayed(message) && self.subscriptions.accepts(message)
+    }
+
+    // Skips messages already replayed when resuming
+    fn is_replayed(&mut self, message: &Message) -> bool {
+        let through = match self.replayed_through {
+            Some(through) => through,
+            None => return false,
+        };
+        match message.id().and_then(position) {
+            Some((_, position)) if position <= through => true,
+            _ => {
+                self.replayed_through = None;
+                false
+            }
+        }
+    }
+}
+
+pub struct Clients<T, C>
+where
+    T: StreamRef + Read + Write,
+    C: Codec,
+{
+    receiver: ReactiveSignalReceiver<Arc<Message>>,
+    connections: HashMap<Token, Client<T, C>>,
+    context: Context,
+    pending: PendingRequests,
+    // Messages of the durable subscriptions consumed by clients of this reactor
+    deliveries: ReactiveSignalReceiver<Delivery>,
+    // Answers to queries made by clients of this reactor
+    query_replies: ReactiveSignalReceiver<QueryReply>,
+    idle_timeout: IdleTimeout,
+    ticker: ReactiveSignalReceiver<()>,
+}
+
+impl<T, C> Clients<T, C>
+where
+    T: StreamRef + Read + Write,
+    C: Codec,
+{
+    pub fn new(receiver: SignalReceiver<Arc<Message>>, context: Context) -> Result<Self> {
+        let timeouts = &context.config.timeouts;
+        let idle_timeout = IdleTimeout::new(timeouts.ping_interval, timeouts.client_idle);
+
+        Ok(Self {
+            receiver: ReactiveSignalReceiver::new(receiver)?,
+            connections: HashMap::new(),
+            context,
+            pending: PendingRequests::new()?,
+            deliveries: ReactiveSignalReceiver::new(Capacity::Unbounded.into())?,
+            query_replies: ReactiveSignalReceiver::new(Capacity::Unbounded.into())?,
+            idle_timeout,
+            ticker: ticker(Duration::from_secs(1))?,
+        })
+    }
+
+    fn tick(&mut self) {
+        if self.idle_timeout.is_enabled() {
+            self.check_idle();
+        }
+
+        for (token, error) in self.pending.expire(&self.context.router) {
+            if let Some(client) = self.connections.get_mut(&token) {
+                client.outbox.send(C::encode(error));
+            }
+        }
+
+        self.context.sessions.expire();
+        self.context.silences.save();
+    }
+
+    // The session of the client, if any, can be resumed from here on
+    fn disconnect(&mut self, token: Token) {
+        self.pending.disconnected(&self.context.router, token);
+        if let Some(client) = self.connections.remove(&token) {
+            for (name, consumer) in &client.consuming {
+                self.context.queues.release(name, *consumer);
+            }
+            if let Some(ref session) = client.session {
+                self.context.sessions.detach(session, client.subscriptions);
+            }
+        }
+    }
+
+    fn check_idle(&mut self) {
+        let idle_timeout = self.idle_timeout;
+        let mut expired = Vec::new();
+        for (token, client) in self.connections.iter_mut() {
+            match client.liveness.check(&idle_timeout) {
+                Idle::Active => {}
+                Idle::Ping => client.outbox.send(C::encode(ping_msg())),
+                Idle::Expired => {
+                    client.outbox.send(C::encode(error_msg("Idle timeout, disconnecting")));
+                    expired.push(*token);
+                }
+            }
+        }
+
+        for token in expired {
+            self.disconnect(token);
+        }
+    }
+}
+
+impl<T, C> Reactor for Clients<T, C>
+where
+    T: StreamRef + Read + Write,
+    C: Codec<Message=Request>,
+{
+    type Input = Session<T>;
+    type Output = ();
+
+    fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
+        match reaction {
+            Reaction::Event(event) => {
+                if event.token() == self.ticker.token() {
+                    while let Ok(()) = self.ticker.try_recv() {}
+                    self.tick();
+                    return Reaction::Continue;
+                }
+
+                if event.token() == self.pending.token() {
+                    while let Some((token, reply)) = self.pending.receive() {
+                        if let Some(client) = self.connections.get_mut(&token) {
+                            client.outbox.send(C::encode(reply));
+                        }
+                    }
+                    return Reaction::Continue;
+                }
+
+                if event.token() == self.query_replies.token() {
+                    while let Ok(reply) = self.query_replies.try_recv() {
+                        if let Some(client) = self.connections.get_mut(&reply.token) {
+                            client.outbox.send(C::encode(reply.message));
+                        }
+                    }
+                    return Reaction::Continue;
+                }
+
+                if event.token() == self.deliveries.token() {
+                    while let Ok(delivery) = self.deliveries.try_recv() {
+                        if let Some(client) = self.connections.get_mut(&delivery.token) {
+                            client.outbox.send(C::encode(&*delivery.message));
+                        }
+                    }
+                    return Reaction::Continue;
+                }
+
+                if event.token() == self.receiver.token() {
+                    let mut disconnect = Vec::new();
+                    while let Ok(message) = self.receiver.try_recv() {
+                        if self.context.silences.is_silenced(&message) {
+                            continue;
+                        }
+                        let bytes = C::encode(&*message);
+                        for (token, client) in self.connections.iter_mut() {
+                            if !client.wants(&message) {
+                                continue;
+                            }
+                            if let Err(Overflow::Disconnect) = client.outbox.push(message.channel(), bytes.clone()) {
+                                // The socket has stalled, so the client most likely never reads this
+                                error!("Disconnecting slow client {:?}, too many messages queued", client.source);
+                                client.outbox.send(C::encode(error_msg("Too many messages queued, disconnecting")));
+                                disconnect.push(*token);
+                            }
+                        }
+                    }
+
+                    for token in disconnect {
+                        self.disconnect(token);
+                    }
+                    for client in self.connections.values_mut() {
+                        client.outbox.pump();
+                    }
+                    return Reaction::Continue;
+                }
+
+                let context = &self.context;
+                let pending = &mut self.pending;
+                let deliveries = &self.deliveries;
+                let query_replies = &self.query_replies;
+                if let Some(client) = self.connections.get_mut(&event.token()) {
+                    let mut requests = VecDeque::new();
+                    let con = client.outbox.connection();
+                    if let Reaction::Value(val) = con.react(event.into()) {
+                        requests.push_back(val);
+                        while let Reaction::Value(val) = con.react(Reaction::Continue) {
+                            requests.push_back(val);
+                        }
+                    }
+
+                    if !requests.is_empty() {
+                        client.liveness.seen();
+                    }
+
+                    for request in requests {
+                        match request {
+                            Ok(request) => {
+                                if let Err(reason) = client.handle(request, context, pending, deliveries, query_replies) {
+                                    client.outbox.send(C::encode(error_msg(&reason)));
+                                }
+                            }
+                            Err(e) => {
+                                error!("{:?}", e);
+                                self.disconnect(event.token());
+                                return Reaction::Continue
+                            }
+                        }
+                    }
+                    client.outbox.pump();
+                    Reaction::Continue
+                } else {
+                    Reaction::Event(event)
+                }
+            }
+            Reaction::Value(session) => {
+                let buf = status_msg("OK");
+                let bytes = C::encode(buf);
+                let mut outbox = Outbox::new(session.stream, self.context.config.client_queue.clone());
+                outbox.send(bytes);
+                let token = outbox.connection().token();
+                let client = Client {
+                    outbox,
+                    subscriptions: Subscriptions::default(),
+                    liveness: Liveness::new(),
+                    source: session.source,
+                    session: None,
+                    replayed_through: None,
+                    consuming: Vec::new(),
+                };
+                self.connections.insert(token, client);
+                Reaction::Continue
+            }
+            Reaction::Continue => Reaction::Continue,
+        }
+    }
+}
@@ -0,0 +1,327 @@
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+use bytes::Bytes;
+use log::error;
+use serde_derive::Deserialize;
+use sonr::net::stream::{Stream, StreamRef};
+use sonr_connection::{Codec, Connection};
+
+// Bytes handed to the connection but not yet written to the socket.
+// Messages are only taken off the queue while below this.
+const WRITE_WINDOW: u64 = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlowConsumerPolicy {
+    DropOldest,
+    DropNewest,
+    Conflate,
+    Disconnect,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct QueueConfig {
+    pub max_messages: usize,
+    pub max_bytes: usize,
+    pub policy: SlowConsumerPolicy,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            max_messages: 10_000,
+            max_bytes: 8 * 1024 * 1024,
+            policy: SlowConsumerPolicy::DropOldest,
+        }
+    }
+}
+
+// Counts the bytes actually written to the underlying stream
+pub struct Metered<T> {
+    inner: T,
+    written: Rc<Cell<u64>>,
+}
+
+impl<T: Read> Read for Metered<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<T: Write> Write for Metered<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written.set(self.written.get() + n as u64);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: StreamRef> StreamRef for Metered<T> {
+    type Evented = T::Evented;
+
+    fn stream_ref(&self) -> &Stream<Self::Evented> {
+        self.inner.stream_ref()
+    }
+
+    fn stream_ref_mut(&mut self) -> &mut Stream<Self::Evented> {
+        self.inner.stream_ref_mut()
+    }
+}
+
+pub enum Overflow {
+    Disconnect,
+}
+
+// The queued messages, with the channel they were published on, kept
+// within `max_messages` and `max_bytes` by the slow consumer policy
+struct Queue {
+    config: QueueConfig,
+    messages: VecDeque<(String, Bytes)>,
+    bytes: usize,
+    dropped: u64,
+}
+
+impl Queue {
+    fn new(config: QueueConfig) -> Self {
+        Self {
+            config,
+            messages: VecDeque::new(),
+            bytes: 0,
+            dropped: 0,
+        }
+    }
+
+    fn push(&mut self, channel: &str, bytes: Bytes) -> Result<(), Overflow> {
+        // Dropping older messages can't make room for this one
+        if bytes.len() > self.config.max_bytes {
+            if let SlowConsumerPolicy::Disconnect = self.config.policy {
+                return Err(Overflow::Disconnect);
+            }
+            self.dropped(1);
+            return Ok(());
+        }
+
+        if self.is_full(bytes.len()) {
+            match self.config.policy {
+                SlowConsumerPolicy::Disconnect => return Err(Overflow::Disconnect),
+                SlowConsumerPolicy::DropNewest => {
+                    self.dropped(1);
+                    return Ok(());
+                }
+                SlowConsumerPolicy::Conflate => {
+                    if let Some(queued) = self.messages.iter_mut().find(|(c, _)| c == channel) {
+                        self.bytes = self.bytes - queued.1.len() + bytes.len();
+                        queued.1 = bytes;
+                        self.dropped(1);
+                        // The newer message can be larger
+                        self.trim();
+                        return Ok(());
+                    }
+                    self.drop_oldest(bytes.len());
+                }
+                SlowConsumerPolicy::DropOldest => self.drop_oldest(bytes.len()),
+            }
+        }
+
+        self.bytes += bytes.len();
+        self.messages.push_back((channel.to_owned(), bytes));
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<Bytes> {
+        let (_, bytes) = self.messages.pop_front()?;
+        self.bytes -= bytes.len();
+        Some(bytes)
+    }
+
+    fn is_full(&self, len: usize) -> bool {
+        self.messages.len() + 1 > self.config.max_messages || self.bytes + len > self.config.max_bytes
+    }
+
+    fn drop_oldest(&mut self, len: usize) {
+        let mut count = 0;
+        while !self.messages.is_empty() && self.is_full(len) {
+            self.pop();
+            count += 1;
+        }
+        self.dropped(count);
+    }
+
+    fn trim(&mut self) {
+        let mut count = 0;
+        while self.bytes > self.config.max_bytes && self.pop().is_some() {
+            count += 1;
+        }
+        self.dropped(count);
+    }
+
+    fn dropped(&mut self, count: u64) {
+        if count > 0 && self.dropped == 0 {
+            error!("Slow client, dropping messages");
+        }
+        self.dropped += count;
+    }
+}
+
+// Bounded queue of outgoing messages in front of a client connection.
+pub struct Outbox<T, C>
+where
+    T: StreamRef + Read + Write,
+    C: Codec,
+{
+    connection: Connection<Metered<T>, C>,
+    queue: Queue,
+    handed: u64,
+    written: Rc<Cell<u64>>,
+}
+
+impl<T, C> Outbox<T, C>
+where
+    T: StreamRef + Read + Write,
+    C: Codec,
+{
+    pub fn new(stream: T, config: QueueConfig) -> Self {
+        let written = Rc::new(Cell::new(0));
+        let stream = Metered {
+            inner: stream,
+            written: written.clone(),
+        };
+
+        Self {
+            connection: Connection::new(stream, C::default()),
+            queue: Queue::new(config),
+            handed: 0,
+            written,
+        }
+    }
+
+    pub fn connection(&mut self) -> &mut Connection<Metered<T>, C> {
+        &mut self.connection
+    }
+
+    // Write straight to the connection, skipping the queue.
+    // Used for replies to the client.
+    pub fn send(&mut self, bytes: Bytes) {
+        self.handed += bytes.len() as u64;
+        self.connection.add_write_buffer(bytes);
+        self.connection.write_buffers();
+    }
+
+    // Queue a message, applying the slow consumer policy if the queue is full
+    pub fn push(&mut self, channel: &str, bytes: Bytes) -> Result<(), Overflow> {
+        self.queue.push(channel, bytes)
+    }
+
+    // Hand queued messages to the connection as the socket drains
+    pub fn pump(&mut self) {
+        while self.handed - self.written.get() < WRITE_WINDOW {
+            match self.queue.pop() {
+                Some(bytes) => {
+                    self.handed += bytes.len() as u64;
+                    self.connection.add_write_buffer(bytes);
+                }
+                None => break,
+            }
+        }
+        self.connection.write_buffers();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(policy: SlowConsumerPolicy) -> Queue {
+        Queue::new(QueueConfig {
+            max_messages: 3,
+            max_bytes: 10,
+            policy,
+        })
+    }
+
+    fn push(queue: &mut Queue, channel: &str, message: &'static str) -> bool {
+        queue.push(channel, Bytes::from_static(message.as_bytes())).is_ok()
+    }
+
+    fn contents(queue: &Queue) -> Vec<&[u8]> {
+        queue.messages.iter().map(|(_, bytes)| bytes.as_ref()).collect()
+    }
+
+    #[test]
+    fn drops_oldest() {
+        let mut queue = queue(SlowConsumerPolicy::DropOldest);
+        for message in &["a", "b", "c", "d"] {
+            assert!(push(&mut queue, "cpu", message));
+        }
+        assert_eq!(contents(&queue), vec![b"b", b"c", b"d"]);
+
+        // Makes room by bytes as well
+        assert!(push(&mut queue, "cpu", "efghijklm"));
+        assert_eq!(contents(&queue), vec![&b"d"[..], b"efghijklm"]);
+        assert_eq!(queue.bytes, 10);
+        assert_eq!(queue.dropped, 3);
+    }
+
+    #[test]
+    fn drops_newest() {
+        let mut queue = queue(SlowConsumerPolicy::DropNewest);
+        for message in &["a", "b", "c", "d"] {
+            assert!(push(&mut queue, "cpu", message));
+        }
+        assert!(push(&mut queue, "cpu", "efghijkl"));
+        assert_eq!(contents(&queue), vec![b"a", b"b", b"c"]);
+        assert_eq!(queue.dropped, 2);
+    }
+
+    #[test]
+    fn conflates_per_channel() {
+        let mut queue = queue(SlowConsumerPolicy::Conflate);
+        assert!(push(&mut queue, "cpu", "a"));
+        assert!(push(&mut queue, "disk", "b"));
+        assert!(push(&mut queue, "mem", "c"));
+
+        // Replaces the queued message of the same channel in place
+        assert!(push(&mut queue, "disk", "d"));
+        assert_eq!(contents(&queue), vec![b"a", b"d", b"c"]);
+        // Without one the oldest is dropped
+        assert!(push(&mut queue, "net", "e"));
+        assert_eq!(contents(&queue), vec![b"d", b"c", b"e"]);
+
+        // A larger replacement still keeps within max_bytes
+        assert!(push(&mut queue, "mem", "fghijklmn"));
+        assert_eq!(contents(&queue), vec![&b"fghijklmn"[..], b"e"]);
+        assert!(queue.bytes <= 10);
+        assert_eq!(queue.bytes, 10);
+    }
+
+    #[test]
+    fn disconnects() {
+        let mut queue = queue(SlowConsumerPolicy::Disconnect);
+        for message in &["a", "b", "c"] {
+            assert!(push(&mut queue, "cpu", message));
+        }
+        assert!(!push(&mut queue, "cpu", "d"));
+        assert_eq!(queue.pop().as_deref(), Some(&b"a"[..]));
+        assert!(push(&mut queue, "cpu", "d"));
+        assert!(!push(&mut queue, "cpu", "efghijklmnop"));
+    }
+
+    #[test]
+    fn never_queues_messages_over_max_bytes() {
+        for &policy in &[SlowConsumerPolicy::DropOldest, SlowConsumerPolicy::DropNewest, SlowConsumerPolicy::Conflate] {
+            let mut queue = queue(policy);
+            assert!(push(&mut queue, "cpu", "a"));
+            assert!(push(&mut queue, "cpu", "bcdefghijkl"));
+            assert_eq!(contents(&queue), vec![b"a"]);
+            assert_eq!(queue.dropped, 1);
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use sonr::errors::Result;
+use sonr::sync::signal::ReactiveSignalReceiver;
+use sonr::sync::Capacity;
+use sonr::Token;
+
+use crate::messages::{error_msg, Message, Payload, Source};
+use crate::router::{Reply, Router};
+
+// A client asking a monitor for something
+#[derive(Debug, Deserialize)]
+pub struct MonitorRequest {
+    // The monitor identity
+    monitor: String,
+    // Set as the correlation_id of the reply
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(flatten)]
+    payload: Payload,
+    // Seconds to wait for the reply
+    #[serde(default = "default_timeout")]
+    timeout: u64,
+}
+
+fn default_timeout() -> u64 {
+    30
+}
+
+struct Waiting {
+    token: Token,
+    id: Option<String>,
+    deadline: Instant,
+}
+
+// Requests waiting for a reply, by correlation id
+#[derive(Default)]
+struct Requests {
+    waiting: HashMap<String, Waiting>,
+}
+
+impl Requests {
+    fn add(&mut self, correlation_id: String, waiting: Waiting) {
+        self.waiting.insert(correlation_id, waiting);
+    }
+
+    // The reply for the client, unless it stopped waiting
+    fn reply(&mut self, reply: Reply) -> Option<(Token, Message)> {
+        let waiting = self.waiting.remove(&reply.correlation_id)?;
+        let mut message = reply.message;
+        message.set_correlation_id(waiting.id);
+        Some((waiting.token, message))
+    }
+
+    fn expired(&self, now: Instant) -> Vec<String> {
+        self.waiting
+            .iter()
+            .filter(|(_, waiting)| waiting.deadline <= now)
+            .map(|(correlation_id, _)| correlation_id.clone())
+            .collect()
+    }
+
+    // Stop waiting, with the error for the client
+    fn time_out(&mut self, correlation_id: &str) -> Option<(Token, Message)> {
+        let waiting = self.waiting.remove(correlation_id)?;
+        let mut error = error_msg("Request timed out");
+        error.set_correlation_id(waiting.id);
+        Some((waiting.token, error))
+    }
+
+    // Stop waiting for the replies of a client, returning their correlation ids
+    fn disconnected(&mut self, token: Token) -> Vec<String> {
+        let gone = self
+            .waiting
+            .iter()
+            .filter(|(_, waiting)| waiting.token == token)
+            .map(|(correlation_id, _)| correlation_id.clone())
+            .collect::<Vec<_>>();
+        for correlation_id in &gone {
+            self.waiting.remove(correlation_id);
+        }
+        gone
+    }
+}
+
+// Requests made by the clients of one reactor that are waiting for a reply
+pub struct PendingRequests {
+    replies: ReactiveSignalReceiver<Reply>,
+    requests: Requests,
+}
+
+impl PendingRequests {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            replies: ReactiveSignalReceiver::new(Capacity::Unbounded.into())?,
+            requests: Requests::default(),
+        })
+    }
+
+    pub fn token(&self) -> Token {
+        self.replies.token()
+    }
+
+    pub fn send(&mut self, router: &Router, token: Token, request: MonitorRequest, source: Source) -> std::result::Result<(), String> {
+        let deadline = Instant::now()
+            .checked_add(Duration::from_secs(request.timeout))
+            .ok_or("Timeout is too large")?;
+        let reply_to = self.replies.sender();
+        let correlation_id = router.request(&request.monitor, request.payload, source, reply_to)?;
+        let waiting = Waiting {
+            token,
+            id: request.id,
+            deadline,
+        };
+        self.requests.add(correlation_id, waiting);
+        Ok(())
+    }
+
+    // A reply and the client it's for, with the client's id as the correlation id
+    pub fn receive(&mut self) -> Option<(Token, Message)> {
+        while let Ok(reply) = self.replies.try_recv() {
+            if let Some(reply) = self.requests.reply(reply) {
+                return Some(reply);
+            }
+        }
+        None
+    }
+
+    // Errors for the clients of requests past their deadline
+    pub fn expire(&mut self, router: &Router) -> Vec<(Token, Message)> {
+        let mut errors = Vec::new();
+        for correlation_id in self.requests.expired(Instant::now()) {
+            // Otherwise the reply is already on its way
+            if !router.cancel(&correlation_id) {
+                continue;
+            }
+            errors.extend(self.requests.time_out(&correlation_id));
+        }
+        errors
+    }
+
+    // Forget the requests of a client that has gone, so replies on their way
+    // never reach a later client given the same token
+    pub fn disconnected(&mut self, router: &Router, token: Token) {
+        for correlation_id in self.requests.disconnected(token) {
+            router.cancel(&correlation_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{MessageType, Severity};
+
+    fn waiting(token: usize, id: &str, deadline: Instant) -> Waiting {
+        Waiting {
+            token: Token(token),
+            id: Some(id.into()),
+            deadline,
+        }
+    }
+
+    fn reply(correlation_id: &str) -> Reply {
+        Reply {
+            correlation_id: correlation_id.into(),
+            message: Message::new("web1", "done".into(), MessageType::Reply, Severity::Info),
+        }
+    }
+
+    #[test]
+    fn correlates_replies() {
+        let mut requests = Requests::default();
+        let later = Instant::now() + Duration::from_secs(30);
+        requests.add("1-1".into(), waiting(4, "rerun", later));
+        requests.add("1-2".into(), waiting(5, "dump", later));
+
+        let (token, message) = requests.reply(reply("1-2")).unwrap();
+        assert_eq!(token, Token(5));
+        assert_eq!(message.correlation_id(), Some("dump"));
+        assert!(requests.reply(reply("1-2")).is_none());
+        assert!(requests.reply(reply("1-3")).is_none());
+
+        let (token, message) = requests.reply(reply("1-1")).unwrap();
+        assert_eq!(token, Token(4));
+        assert_eq!(message.correlation_id(), Some("rerun"));
+    }
+
+    #[test]
+    fn times_out() {
+        let mut requests = Requests::default();
+        let now = Instant::now();
+        requests.add("1-1".into(), waiting(4, "rerun", now));
+        requests.add("1-2".into(), waiting(5, "dump", now + Duration::from_secs(30)));
+
+        assert_eq!(requests.expired(now), vec!["1-1".to_string()]);
+        let (token, error) = requests.time_out("1-1").unwrap();
+        assert_eq!(token, Token(4));
+        assert_eq!(error.correlation_id(), Some("rerun"));
+        // A reply after the error is dropped
+        assert!(requests.reply(reply("1-1")).is_none());
+        assert!(requests.expired(now).is_empty());
+    }
+
+    #[test]
+    fn forgets_disconnected_clients() {
+        let mut requests = Requests::default();
+        let later = Instant::now() + Duration::from_secs(30);
+        requests.add("1-1".into(), waiting(4, "rerun", later));
+        requests.add("1-2".into(), waiting(5, "dump", later));
+        requests.add("1-3".into(), waiting(4, "dump", later));
+
+        let mut gone = requests.disconnected(Token(4));
+        gone.sort();
+        assert_eq!(gone, vec!["1-1".to_string(), "1-3".to_string()]);
+        // A new client with the token never sees the replies
+        assert!(requests.reply(reply("1-1")).is_none());
+        assert!(requests.reply(reply("1-2")).is_some());
+    }
+}
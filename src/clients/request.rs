@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use super::pending::MonitorRequest;
+use super::subscription::Subscription;
+use crate::alerts::NewSilence;
+use crate::messages::Message;
+use crate::rollups::Query;
+
+// Commands sent by a client after authenticating
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    Subscribe(Subscription),
+    Unsubscribe {
+        #[serde(default)]
+        id: Option<String>,
+    },
+    // List the monitors currently online, those with matching tags if given
+    Monitors {
+        #[serde(default)]
+        tags: Option<String>,
+    },
+    Silence(NewSilence),
+    Acknowledge(NewSilence),
+    Unsilence {
+        id: String,
+    },
+    Silences,
+    Query(Query),
+    // Ask a monitor for something, the reply is sent back to the client
+    #[serde(rename = "request")]
+    Ask(MonitorRequest),
+    // Start a session to resume after reconnecting
+    Session,
+    // Take over a session, receiving the messages published after `last_id`,
+    // or after the sequence of each channel
+    Resume {
+        session: String,
+        #[serde(default)]
+        last_id: Option<String>,
+        #[serde(default)]
+        sequence: Option<HashMap<String, u64>>,
+    },
+    // Receive the messages of a durable subscription until disconnecting
+    Consume {
+        name: String,
+    },
+    // Acknowledge a message of the durable subscriptions being consumed
+    Ack {
+        id: String,
+    },
+    // Publish a message as if sent by a monitor
+    Publish {
+        message: Message,
+    },
+    Ping,
+    Pong,
+}
@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use serde_derive::Deserialize;
+use serde_json::json;
+
+use super::subscription::Subscriptions;
+use crate::messages::{Message, MessageType, Severity, Source};
+
+pub const SESSION_CHANNEL: &str = "SESSION";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SessionConfig {
+    // Seconds a disconnected session, and the messages it could miss, are
+    // kept for. 0 disables sessions. Needs `timeouts.client_idle`, as a
+    // session can't be resumed before its connection is closed.
+    pub retention: u64,
+    // Most messages kept for resuming sessions
+    pub max_messages: usize,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            retention: 0,
+            max_messages: 10_000,
+        }
+    }
+}
+
+struct Session {
+    identity: Option<String>,
+    // Set while no connection is using the session
+    detached: Option<(Instant, Subscriptions)>,
+}
+
+// Sessions clients can resume after reconnecting, on any thread
+#[derive(Clone)]
+pub struct Sessions {
+    retention: Duration,
+    sessions: Arc<Mutex<HashMap<String, Session>>>,
+}
+
+impl Sessions {
+    pub fn new(config: &SessionConfig) -> Self {
+        Self {
+            retention: Duration::from_secs(config.retention),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn sessions(&self) -> MutexGuard<'_, HashMap<String, Session>> {
+        match self.sessions.lock() {
+            Ok(sessions) => sessions,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    // Returns the token to resume the session with
+    pub fn start(&self, source: &Source) -> Result<String, String> {
+        if self.retention == Duration::from_secs(0) {
+            return Err("Sessions are not enabled".into());
+        }
+        let token = random_token().map_err(|e| format!("Failed to start session: {}", e))?;
+        let session = Session {
+            identity: source.identity.clone(),
+            detached: None,
+        };
+        self.sessions().insert(token.clone(), session);
+        Ok(token)
+    }
+
+    // Take over a detached session, returning its subscriptions
+    pub fn resume(&self, token: &str, source: &Source) -> Result<Subscriptions, String> {
+        let mut sessions = self.sessions();
+        let session = match sessions.get_mut(token) {
+            Some(session) if session.identity == source.identity => session,
+            _ => return Err("Unknown session".into()),
+        };
+        match session.detached.take() {
+            Some((_, subscriptions)) => Ok(subscriptions),
+            None => Err("Session is in use".into()),
+        }
+    }
+
+    // The connection using the session is gone
+    pub fn detach(&self, token: &str, subscriptions: Subscriptions) {
+        if let Some(session) = self.sessions().get_mut(token) {
+            session.detached = Some((Instant::now(), subscriptions));
+        }
+    }
+
+    pub fn end(&self, token: &str) {
+        self.sessions().remove(token);
+    }
+
+    pub fn expire(&self) {
+        let retention = self.retention;
+        self.sessions().retain(|_, session| match session.detached {
+            Some((at, _)) => at.elapsed() < retention,
+            None => true,
+        });
+    }
+}
+
+fn random_token() -> std::io::Result<String> {
+    let mut bytes = [0u8; 16];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+pub fn started_msg(token: &str) -> Message {
+    let payload = json!({ "session": token, "status": "started" });
+    Message::new(SESSION_CHANNEL, payload.into(), MessageType::System, Severity::Info)
+}
+
+// Sent after the missed messages, live delivery follows
+pub fn resumed_msg(token: &str, replayed: usize, complete: bool) -> Message {
+    let payload = json!({
+        "session": token,
+        "status": "resumed",
+        "replayed": replayed,
+        "complete": complete,
+    });
+    Message::new(SESSION_CHANNEL, payload.into(), MessageType::System, Severity::Info)
+}
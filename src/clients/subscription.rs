@@ -0,0 +1,182 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::filter::Filter;
+use crate::messages::{Message, Selector, Severity};
+
+#[derive(Debug, Deserialize)]
+pub struct Subscription {
+    #[serde(default)]
+    id: Option<String>,
+    // No channels means every channel
+    #[serde(default)]
+    channels: Vec<String>,
+    // Messages from monitors registered with matching tags, e.g. `env=prod,service=api`
+    #[serde(default, rename = "tags")]
+    tags_source: Option<String>,
+    #[serde(skip)]
+    selector: Option<Selector>,
+    #[serde(default)]
+    min_severity: Option<Severity>,
+    #[serde(default, rename = "filter")]
+    filter_source: Option<String>,
+    #[serde(skip)]
+    filter: Option<Filter>,
+    // At most this many messages per second on each channel
+    #[serde(default)]
+    max_rate: Option<f64>,
+    // Only every nth message on each channel
+    #[serde(default)]
+    sample: Option<u64>,
+    #[serde(skip)]
+    channels_seen: HashMap<String, ChannelState>,
+}
+
+#[derive(Debug, Default)]
+struct ChannelState {
+    seen: u64,
+    last_sent: Option<Instant>,
+}
+
+impl Subscription {
+    fn accepts(&mut self, message: &Message) -> bool {
+        if !self.channels.is_empty() && !self.channels.iter().any(|c| c == message.channel()) {
+            return false;
+        }
+
+        if let Some(ref selector) = self.selector {
+            if !selector.matches(message.metadata()) {
+                return false;
+            }
+        }
+
+        if let Some(min) = self.min_severity {
+            if message.severity() < min {
+                return false;
+            }
+        }
+
+        if let Some(ref filter) = self.filter {
+            if !filter.matches(message) {
+                return false;
+            }
+        }
+
+        if self.sample.is_none() && self.max_rate.is_none() {
+            return true;
+        }
+
+        let state = self
+            .channels_seen
+            .entry(message.channel().to_owned())
+            .or_default();
+
+        state.seen += 1;
+        if let Some(n) = self.sample {
+            if !(state.seen - 1).is_multiple_of(n) {
+                return false;
+            }
+        }
+
+        if let Some(rate) = self.max_rate {
+            let interval = Duration::from_nanos((1_000_000_000.0 / rate) as u64);
+            match state.last_sent {
+                Some(last) if last.elapsed() < interval => return false,
+                _ => state.last_sent = Some(Instant::now()),
+            }
+        }
+
+        true
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        match (self.sample, self.max_rate) {
+            (Some(0), _) => Err("sample must be at least 1".into()),
+            (_, Some(rate)) if rate.partial_cmp(&0.0) != Some(Ordering::Greater) => Err("max_rate must be greater than 0".into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+// The subscriptions of a single client connection.
+// A client without any subscriptions receives every message.
+#[derive(Debug, Default)]
+pub struct Subscriptions {
+    subscriptions: Vec<Subscription>,
+}
+
+impl Subscriptions {
+    pub fn subscribe(&mut self, mut subscription: Subscription) -> Result<(), String> {
+        subscription.validate()?;
+        if let Some(ref source) = subscription.filter_source {
+            subscription.filter = Some(Filter::parse(source)?);
+        }
+        if let Some(ref source) = subscription.tags_source {
+            subscription.selector = Some(Selector::parse(source)?);
+        }
+        if subscription.id.is_some() {
+            self.subscriptions.retain(|s| s.id != subscription.id);
+        }
+        self.subscriptions.push(subscription);
+        Ok(())
+    }
+
+    // Remove a subscription by id, or all of them
+    pub fn unsubscribe(&mut self, id: Option<String>) {
+        match id {
+            Some(id) => self.subscriptions.retain(|s| s.id.as_ref() != Some(&id)),
+            None => self.subscriptions.clear(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscriptions.is_empty()
+    }
+
+    pub fn accepts(&mut self, message: &Message) -> bool {
+        self.subscriptions.is_empty() || self.subscriptions.iter_mut().any(|s| s.accepts(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::messages::{Metadata, MessageType};
+
+    fn subscription(json: &str) -> Subscription {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn message(channel: &str, tags: Option<&[(&str, &str)]>) -> Message {
+        let mut message = Message::new(channel, "up".into(), MessageType::Status, Severity::Info);
+        let metadata = tags.map(|tags| Metadata {
+            environment: Some("prod".into()),
+            tags: tags.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            ..Metadata::default()
+        });
+        message.set_metadata(metadata.map(Arc::new));
+        message
+    }
+
+    #[test]
+    fn selects_by_tags() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.subscribe(subscription(r#"{"tags": "env=prod,region=eu"}"#)).unwrap();
+
+        assert!(subscriptions.accepts(&message("api-7f3a", Some(&[("region", "eu")]))));
+        assert!(!subscriptions.accepts(&message("api-7f3a", Some(&[("region", "us")]))));
+        // Not registered yet
+        assert!(!subscriptions.accepts(&message("api-7f3a", None)));
+
+        subscriptions.subscribe(subscription(r#"{"channels": ["db1"], "tags": "env=prod"}"#)).unwrap();
+        assert!(subscriptions.accepts(&message("db1", Some(&[]))));
+        assert!(!subscriptions.accepts(&message("db2", Some(&[("region", "us")]))));
+
+        assert!(subscriptions.subscribe(subscription(r#"{"tags": "env"}"#)).is_err());
+    }
+}
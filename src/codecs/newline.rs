@@ -50,7 +50,7 @@ impl<T: DeserializeOwned> Codec for LineCodec<T> {
 
     fn drain(&mut self) -> VecDeque<Result<T, CodecError>> {
         let mut vec = VecDeque::new();
-        for line in self.lines.drain(..).into_iter() {
+        for line in self.lines.drain(..) {
             let res = serde_json::from_slice(&line);
             match res {
                 Ok(val) => vec.push_back(Ok(val)),
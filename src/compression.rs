@@ -0,0 +1,358 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::mem;
+
+use bytes::Bytes;
+use flate2::write::{DeflateDecoder, DeflateEncoder};
+use serde::{Deserialize, Serialize};
+use sonr::net::stream::{Stream, StreamRef};
+
+const READ_BUFFER_SIZE: usize = 1024;
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    None,
+    Deflate,
+    Zstd,
+}
+
+impl Compression {
+    // Pick the first algorithm requested by the peer that is also allowed
+    // by the server, falling back to no compression.
+    pub fn negotiate(requested: &[Compression], allowed: &[Compression]) -> Compression {
+        requested
+            .iter()
+            .find(|c| allowed.contains(c))
+            .cloned()
+            .unwrap_or(Compression::None)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Deflate => "deflate",
+            Compression::Zstd => "zstd",
+        }
+    }
+}
+
+enum Encoder {
+    Deflate(DeflateEncoder<Vec<u8>>),
+    Zstd(zstd::stream::write::Encoder<Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(compression: Compression) -> io::Result<Option<Self>> {
+        let encoder = match compression {
+            Compression::None => None,
+            Compression::Deflate => Some(Encoder::Deflate(DeflateEncoder::new(
+                Vec::new(),
+                flate2::Compression::default(),
+            ))),
+            Compression::Zstd => Some(Encoder::Zstd(zstd::stream::write::Encoder::new(
+                Vec::new(),
+                ZSTD_LEVEL,
+            )?)),
+        };
+        Ok(encoder)
+    }
+
+    // Compress and flush, so the peer can decode everything
+    // written so far without waiting for more data.
+    fn compress(&mut self, buf: &[u8]) -> io::Result<Vec<u8>> {
+        let out = match self {
+            Encoder::Deflate(e) => {
+                e.write_all(buf)?;
+                e.flush()?;
+                e.get_mut()
+            }
+            Encoder::Zstd(e) => {
+                e.write_all(buf)?;
+                e.flush()?;
+                e.get_mut()
+            }
+        };
+        Ok(mem::take(out))
+    }
+}
+
+enum Decoder {
+    Deflate(DeflateDecoder<Vec<u8>>),
+    Zstd(zstd::stream::write::Decoder<Vec<u8>>),
+}
+
+impl Decoder {
+    fn new(compression: Compression) -> io::Result<Option<Self>> {
+        let decoder = match compression {
+            Compression::None => None,
+            Compression::Deflate => Some(Decoder::Deflate(DeflateDecoder::new(Vec::new()))),
+            Compression::Zstd => Some(Decoder::Zstd(zstd::stream::write::Decoder::new(
+                Vec::new(),
+            )?)),
+        };
+        Ok(decoder)
+    }
+
+    fn decompress(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self {
+            Decoder::Deflate(d) => {
+                d.write_all(buf)?;
+                d.flush()
+            }
+            Decoder::Zstd(d) => {
+                d.write_all(buf)?;
+                d.flush()
+            }
+        }
+    }
+
+    fn output(&mut self) -> &mut Vec<u8> {
+        match self {
+            Decoder::Deflate(d) => d.get_mut(),
+            Decoder::Zstd(d) => d.get_mut(),
+        }
+    }
+}
+
+// Sits between the (tls) stream and the codec, compressing everything
+// written and decompressing everything read.
+//
+// Compressed output that could not be written because the underlying
+// stream would block is kept and written ahead of the next write. The write
+// still reports `WouldBlock`, so the caller keeps its buffer and writes again
+// once the stream is writable, and `accepted` tracks how much of that buffer
+// was already compressed.
+pub struct Compressed<T> {
+    inner: T,
+    encoder: Option<Encoder>,
+    decoder: Option<Decoder>,
+    pending: Vec<u8>,
+    accepted: usize,
+}
+
+impl<T: Read + Write> Compressed<T> {
+    pub fn new(inner: T, compression: Compression) -> io::Result<Self> {
+        Ok(Self {
+            inner,
+            encoder: Encoder::new(compression)?,
+            decoder: Decoder::new(compression)?,
+            pending: Vec::new(),
+            accepted: 0,
+        })
+    }
+
+    // Queue bytes to be written uncompressed ahead of any compressed output,
+    // used to tell the peer the outcome of the negotiation.
+    pub fn preamble(&mut self, bytes: Bytes) {
+        self.pending.extend_from_slice(&bytes);
+    }
+
+    fn write_pending(&mut self) -> io::Result<()> {
+        while !self.pending.is_empty() {
+            match self.inner.write(&self.pending)? {
+                0 => return Err(ErrorKind::WriteZero.into()),
+                n => {
+                    self.pending.drain(..n);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: Read + Write> Read for Compressed<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let decoder = match self.decoder {
+            Some(ref mut d) => d,
+            None => return self.inner.read(buf),
+        };
+
+        while decoder.output().is_empty() {
+            let mut raw = [0u8; READ_BUFFER_SIZE];
+            let n = self.inner.read(&mut raw)?;
+            if n == 0 {
+                return Ok(0);
+            }
+            decoder.decompress(&raw[..n])?;
+        }
+
+        let output = decoder.output();
+        let n = buf.len().min(output.len());
+        buf[..n].copy_from_slice(&output[..n]);
+        output.drain(..n);
+        Ok(n)
+    }
+}
+
+impl<T: Read + Write> Write for Compressed<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_pending()?;
+
+        if self.accepted > 0 {
+            let n = self.accepted.min(buf.len());
+            self.accepted -= n;
+            return Ok(n);
+        }
+
+        let compressed = match self.encoder {
+            Some(ref mut e) => e.compress(buf)?,
+            None => return self.inner.write(buf),
+        };
+        self.pending.extend_from_slice(&compressed);
+
+        match self.write_pending() {
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                self.accepted = buf.len();
+                Err(ErrorKind::WouldBlock.into())
+            }
+            Err(e) => Err(e),
+            Ok(()) => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_pending()?;
+        self.inner.flush()
+    }
+}
+
+impl<T: StreamRef> StreamRef for Compressed<T> {
+    type Evented = T::Evented;
+
+    fn stream_ref(&self) -> &Stream<Self::Evented> {
+        self.inner.stream_ref()
+    }
+
+    fn stream_ref_mut(&mut self) -> &mut Stream<Self::Evented> {
+        self.inner.stream_ref_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An in memory stream whose writes block while `blocked` is set
+    #[derive(Default)]
+    struct Mock {
+        input: Vec<u8>,
+        output: Vec<u8>,
+        blocked: bool,
+    }
+
+    impl Read for Mock {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.input.is_empty() {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            let n = buf.len().min(self.input.len());
+            buf[..n].copy_from_slice(&self.input[..n]);
+            self.input.drain(..n);
+            Ok(n)
+        }
+    }
+
+    impl Write for Mock {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.blocked {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn decompress(compression: Compression, bytes: &[u8]) -> Vec<u8> {
+        let mut decoder = Decoder::new(compression).unwrap().unwrap();
+        decoder.decompress(bytes).unwrap();
+        mem::take(decoder.output())
+    }
+
+    #[test]
+    fn round_trips() {
+        for &compression in &[Compression::Deflate, Compression::Zstd] {
+            let mut encoder = Encoder::new(compression).unwrap().unwrap();
+            let mut decoder = Decoder::new(compression).unwrap().unwrap();
+            for line in &["first line\n", "second line\n"] {
+                let compressed = encoder.compress(line.as_bytes()).unwrap();
+                decoder.decompress(&compressed).unwrap();
+                // Each write can be decoded on its own, without waiting for more
+                assert_eq!(mem::take(decoder.output()), line.as_bytes());
+            }
+        }
+    }
+
+    #[test]
+    fn no_compression() {
+        assert!(Encoder::new(Compression::None).unwrap().is_none());
+        assert!(Decoder::new(Compression::None).unwrap().is_none());
+    }
+
+    #[test]
+    fn negotiates_first_allowed() {
+        let allowed = [Compression::Deflate, Compression::Zstd];
+        let requested = [Compression::Zstd, Compression::Deflate];
+        assert_eq!(Compression::negotiate(&requested, &allowed), Compression::Zstd);
+        assert_eq!(Compression::negotiate(&[Compression::Zstd], &[Compression::Deflate]), Compression::None);
+        assert_eq!(Compression::negotiate(&[], &allowed), Compression::None);
+    }
+
+    #[test]
+    fn unknown_algorithm() {
+        assert!(serde_json::from_str::<Vec<Compression>>(r#"["brotli"]"#).is_err());
+        let requested: Vec<Compression> = serde_json::from_str(r#"["deflate"]"#).unwrap();
+        assert_eq!(Compression::negotiate(&requested, &[]), Compression::None);
+    }
+
+    #[test]
+    fn blocked_write_is_reported() {
+        let mock = Mock {
+            blocked: true,
+            ..Mock::default()
+        };
+        let mut stream = Compressed::new(mock, Compression::Deflate).unwrap();
+
+        let err = stream.write(b"hello\n").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WouldBlock);
+        // Still blocked, the caller has to keep waiting
+        assert_eq!(stream.write(b"hello\n").unwrap_err().kind(), ErrorKind::WouldBlock);
+
+        stream.inner.blocked = false;
+        // Already compressed, only written out now
+        assert_eq!(stream.write(b"hello\n").unwrap(), 6);
+        assert_eq!(stream.write(b"world\n").unwrap(), 6);
+        assert_eq!(decompress(Compression::Deflate, &stream.inner.output), b"hello\nworld\n");
+    }
+
+    #[test]
+    fn preamble_is_not_compressed() {
+        let mut stream = Compressed::new(Mock::default(), Compression::Zstd).unwrap();
+        stream.preamble(Bytes::from_static(b"status\n"));
+        stream.write_all(b"hello\n").unwrap();
+
+        let output = &stream.inner.output;
+        assert_eq!(&output[..7], b"status\n");
+        assert_eq!(decompress(Compression::Zstd, &output[7..]), b"hello\n");
+    }
+
+    #[test]
+    fn reads_decompressed() {
+        let mut encoder = Encoder::new(Compression::Zstd).unwrap().unwrap();
+        let mock = Mock {
+            input: encoder.compress(b"hello\n").unwrap(),
+            ..Mock::default()
+        };
+        let mut stream = Compressed::new(mock, Compression::Zstd).unwrap();
+
+        let mut buf = [0u8; 3];
+        assert_eq!(stream.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf, b"hel");
+        assert_eq!(stream.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf, b"lo\n");
+    }
+}
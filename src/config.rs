@@ -1,9 +1,23 @@
 use std::fs;
+use std::io;
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use sonr::reactor::{Reactor, Reaction};
 use sonr::errors::Result;
 use serde_derive::Deserialize;
 
+use crate::aggregates::AggregateConfig;
+use crate::alerts::{AnomalyConfig, DeadmanConfig, RuleConfig};
+use crate::auth::Permissions;
+use crate::clients::{QueueConfig, SessionConfig};
+use crate::compression::Compression;
+use crate::durable::DurableConfig;
+use crate::messages::is_reserved;
+use crate::ratelimit::PublishLimits;
+use crate::rollups::RollupConfig;
+use crate::sinks::SinkConfig;
+use crate::timer::Timeouts;
+
 #[derive(Clone, Deserialize, Debug)]
 pub struct Config {
     pub auth: HashMap<String, String>,
@@ -14,6 +28,37 @@ pub struct Config {
     pub pfx_cert_path: String,
     pub pfx_pass: String,
     pub thread_count: usize,
+    // Where state that survives a restart is kept
+    pub data_dir: Option<String>,
+    #[serde(default)]
+    pub permissions: Permissions,
+    #[serde(default)]
+    pub compression: Vec<Compression>,
+    // Unix domain socket connections start with a compression handshake
+    #[serde(default)]
+    pub uds_compression: bool,
+    #[serde(default)]
+    pub client_queue: QueueConfig,
+    #[serde(default)]
+    pub sessions: SessionConfig,
+    #[serde(default)]
+    pub publish_limits: PublishLimits,
+    #[serde(default)]
+    pub timeouts: Timeouts,
+    #[serde(default)]
+    pub deadman: DeadmanConfig,
+    #[serde(default)]
+    pub rules: Vec<RuleConfig>,
+    #[serde(default)]
+    pub anomalies: Vec<AnomalyConfig>,
+    #[serde(default)]
+    pub sinks: Vec<SinkConfig>,
+    #[serde(default)]
+    pub aggregates: Vec<AggregateConfig>,
+    #[serde(default)]
+    pub rollups: RollupConfig,
+    #[serde(default)]
+    pub durables: Vec<DurableConfig>,
 }
 
 impl Config {
@@ -45,6 +90,29 @@ impl Config {
     pub fn tcp_monitor_host(&self) -> &str {
         self.tcp_monitor_host.as_ref().unwrap()
     }
+
+    pub fn data_path(&self, file_name: &str) -> Option<PathBuf> {
+        self.data_dir.as_ref().map(|dir| Path::new(dir).join(file_name))
+    }
+
+    pub fn check(&self) -> io::Result<()> {
+        if self.sessions.retention > 0 && self.timeouts.client_idle.is_none() {
+            let reason = "sessions need timeouts.client_idle, to close connections that are gone";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, reason));
+        }
+        if let Some(aggregate) = self.aggregates.iter().find(|a| is_reserved(&a.channel)) {
+            let reason = format!("aggregate channel \"{}\" is reserved for the server", aggregate.channel);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, reason));
+        }
+        Ok(())
+    }
+
+    // For what can't work without a data directory
+    pub fn required_data_path(&self, file_name: &str, feature: &str) -> io::Result<PathBuf> {
+        self.data_path(file_name).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("{} need a data_dir", feature))
+        })
+    }
 }
 
 pub struct Optional<T: Reactor> {
@@ -62,6 +130,12 @@ impl<T: Reactor> Optional<T> {
     }
 }
 
+impl<T: Reactor> From<Option<T>> for Optional<T> {
+    fn from(reactor: Option<T>) -> Self {
+        Self { reactor }
+    }
+}
+
 impl<T: Reactor> Reactor for Optional<T> {
     type Output = T::Output;
     type Input = T::Input;
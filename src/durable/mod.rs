@@ -0,0 +1,490 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use log::error;
+use serde_derive::Deserialize;
+use sonr::errors::Result;
+use sonr::reactor::{Reaction, Reactor};
+use sonr::sync::signal::{ReactiveSignalReceiver, SignalReceiver, SignalSender};
+use sonr::Token;
+
+use crate::filter::{glob, Filter};
+use crate::messages::{error_msg, Message};
+use crate::timer::ticker;
+
+mod store;
+use store::Log;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DurableConfig {
+    // Consumers attach by name, letters, digits, `-` and `_`
+    pub name: String,
+    // Channel patterns, `*` matches any number of characters.
+    // No channels means every channel.
+    #[serde(default)]
+    pub channels: Vec<String>,
+    // A filter expression
+    #[serde(default)]
+    pub filter: Option<String>,
+    // Seconds before an unacknowledged message is delivered again
+    #[serde(default = "default_ack_timeout")]
+    pub ack_timeout: u64,
+    // Messages delivered and not acknowledged yet, anything more waits
+    #[serde(default = "default_max_in_flight")]
+    pub max_in_flight: usize,
+    // Messages kept, the oldest are dropped beyond this
+    #[serde(default = "default_max_messages")]
+    pub max_messages: usize,
+}
+
+fn default_ack_timeout() -> u64 {
+    30
+}
+
+fn default_max_in_flight() -> usize {
+    100
+}
+
+fn default_max_messages() -> usize {
+    100_000
+}
+
+// A message for a consumer, on the thread of its connection
+pub struct Delivery {
+    pub token: Token,
+    pub message: Arc<Message>,
+}
+
+struct Consumer {
+    id: u64,
+    token: Token,
+    sender: SignalSender<Delivery>,
+}
+
+struct Pending {
+    message: Arc<Message>,
+    delivered_at: Option<Instant>,
+}
+
+// The messages of a durable subscription waiting to be acknowledged, and who
+// is consuming them. Kept in memory, the log is written by `Durables`.
+struct Durable {
+    ack_timeout: Duration,
+    max_in_flight: usize,
+    max_messages: usize,
+    // In the order they were added
+    pending: BTreeMap<u64, Pending>,
+    // The key in `pending` of every message by id
+    keys: HashMap<String, u64>,
+    next_key: u64,
+    // Ids acknowledged or dropped since the log was last written
+    acked: Vec<String>,
+    consumer: Option<Consumer>,
+    full: bool,
+}
+
+impl Durable {
+    fn new(config: &DurableConfig, messages: VecDeque<Arc<Message>>) -> Durable {
+        let mut durable = Durable {
+            ack_timeout: Duration::from_secs(config.ack_timeout),
+            max_in_flight: config.max_in_flight,
+            max_messages: config.max_messages,
+            pending: BTreeMap::new(),
+            keys: HashMap::new(),
+            next_key: 0,
+            acked: Vec::new(),
+            consumer: None,
+            full: false,
+        };
+        for message in messages {
+            durable.add(message);
+        }
+        durable.full = false;
+        durable
+    }
+
+    // Returns whether the oldest message was dropped to make room
+    fn add(&mut self, message: Arc<Message>) -> bool {
+        let id = match message.id() {
+            Some(id) => id.to_owned(),
+            None => return false,
+        };
+        self.next_key += 1;
+        self.keys.insert(id, self.next_key);
+        self.pending.insert(self.next_key, Pending { message, delivered_at: None });
+
+        if self.pending.len() <= self.max_messages {
+            self.full = false;
+            return false;
+        }
+        let first = self.pending.keys().next().cloned();
+        if let Some(dropped) = first.and_then(|key| self.pending.remove(&key)) {
+            self.forget(&dropped.message);
+        }
+        let newly_full = !self.full;
+        self.full = true;
+        newly_full
+    }
+
+    fn ack(&mut self, id: &str) {
+        let key = match self.keys.get(id) {
+            Some(key) => *key,
+            None => return,
+        };
+        if let Some(pending) = self.pending.remove(&key) {
+            self.forget(&pending.message);
+        }
+    }
+
+    // Done with a message, the log records it as acknowledged
+    fn forget(&mut self, message: &Message) {
+        if let Some(id) = message.id() {
+            self.keys.remove(id);
+            self.acked.push(id.to_owned());
+        }
+    }
+
+    // Send the consumer what it hasn't got yet, or hasn't acknowledged in time
+    fn deliver(&mut self) {
+        let consumer = match self.consumer {
+            Some(ref consumer) => consumer,
+            None => return,
+        };
+
+        // Messages are delivered in order, so those in flight are mostly first
+        let ack_timeout = self.ack_timeout;
+        let mut in_flight = 0;
+        for pending in self.pending.values_mut() {
+            if in_flight >= self.max_in_flight {
+                break;
+            }
+            if pending.delivered_at.is_some_and(|at| at.elapsed() < ack_timeout) {
+                in_flight += 1;
+                continue;
+            }
+
+            let delivery = Delivery {
+                token: consumer.token,
+                message: pending.message.clone(),
+            };
+            if consumer.sender.send(delivery).is_err() {
+                return;
+            }
+            pending.delivered_at = Some(Instant::now());
+            in_flight += 1;
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    durables: HashMap<String, Durable>,
+    next_consumer: u64,
+}
+
+// Durable subscriptions, the messages their consumers haven't acknowledged
+// and who is consuming them, shared between all threads.
+#[derive(Clone, Default)]
+pub struct Queues {
+    state: Arc<Mutex<State>>,
+}
+
+impl Queues {
+    fn state(&self) -> MutexGuard<'_, State> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    // Attach a consumer, returning the id to release it with. A connection
+    // still consuming is told it no longer is, as it may be gone without its
+    // connection being closed yet. Messages delivered to an earlier consumer
+    // and not acknowledged are delivered again.
+    pub fn consume(&self, name: &str, token: Token, sender: SignalSender<Delivery>) -> std::result::Result<u64, String> {
+        let mut state = self.state();
+        state.next_consumer += 1;
+        let id = state.next_consumer;
+
+        let durable = state
+            .durables
+            .get_mut(name)
+            .ok_or_else(|| format!("Unknown durable subscription \"{}\"", name))?;
+        if let Some(old) = durable.consumer.replace(Consumer { id, token, sender }) {
+            let reason = format!("Durable subscription \"{}\" is consumed by another connection now", name);
+            let delivery = Delivery {
+                token: old.token,
+                message: Arc::new(error_msg(&reason)),
+            };
+            let _ = old.sender.send(delivery);
+        }
+
+        for pending in durable.pending.values_mut() {
+            pending.delivered_at = None;
+        }
+        durable.deliver();
+        Ok(id)
+    }
+
+    pub fn release(&self, name: &str, consumer: u64) {
+        if let Some(durable) = self.state().durables.get_mut(name) {
+            if durable.consumer.as_ref().map(|c| c.id) == Some(consumer) {
+                durable.consumer = None;
+            }
+        }
+    }
+
+    pub fn ack(&self, name: &str, consumer: u64, id: &str) {
+        let mut state = self.state();
+        if let Some(durable) = state.durables.get_mut(name) {
+            if durable.consumer.as_ref().map(|c| c.id) == Some(consumer) {
+                durable.ack(id);
+                durable.deliver();
+            }
+        }
+    }
+
+    // Messages already written to the log
+    fn add(&self, name: &str, messages: Vec<Arc<Message>>) {
+        if let Some(durable) = self.state().durables.get_mut(name) {
+            let mut dropped = false;
+            for message in messages {
+                dropped |= durable.add(message);
+            }
+            if dropped {
+                error!("Durable subscription \"{}\" is full, dropping the oldest messages", name);
+            }
+            durable.deliver();
+        }
+    }
+
+    fn take_acked(&self, name: &str) -> Vec<String> {
+        match self.state().durables.get_mut(name) {
+            Some(durable) => mem::take(&mut durable.acked),
+            None => Vec::new(),
+        }
+    }
+
+    fn pending(&self, name: &str) -> usize {
+        self.state().durables.get(name).map_or(0, |durable| durable.pending.len())
+    }
+
+    fn messages(&self, name: &str) -> Vec<Arc<Message>> {
+        match self.state().durables.get(name) {
+            Some(durable) => durable.pending.values().map(|p| p.message.clone()).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn deliver(&self) {
+        for durable in self.state().durables.values_mut() {
+            durable.deliver();
+        }
+    }
+}
+
+// Writes the messages a durable subscription keeps, and their
+// acknowledgements, to its log
+struct Writer {
+    name: String,
+    channels: Vec<String>,
+    filter: Option<Filter>,
+    log: Log,
+}
+
+impl Writer {
+    // Returns the messages kept in the log
+    fn open(config: &DurableConfig, dir: &Path) -> std::result::Result<(Writer, VecDeque<Arc<Message>>), String> {
+        let valid_name = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if config.name.is_empty() || !config.name.chars().all(valid_name) {
+            return Err("name must be letters, digits, - and _".into());
+        }
+        if config.ack_timeout == 0 || config.max_in_flight == 0 || config.max_messages == 0 {
+            return Err("ack_timeout, max_in_flight and max_messages must be greater than 0".into());
+        }
+        let filter = match config.filter {
+            Some(ref filter) => Some(Filter::parse(filter)?),
+            None => None,
+        };
+
+        let path = dir.join(format!("{}.jsonl", config.name));
+        let (log, messages) = Log::open(path).map_err(|e| e.to_string())?;
+        let writer = Writer {
+            name: config.name.clone(),
+            channels: config.channels.clone(),
+            filter,
+            log,
+        };
+        Ok((writer, messages))
+    }
+
+    fn matches(&self, message: &Message) -> bool {
+        if !self.channels.is_empty() && !self.channels.iter().any(|c| glob(c, message.channel())) {
+            return false;
+        }
+        self.filter.as_ref().is_none_or(|filter| filter.matches(message))
+    }
+
+    fn append(&mut self, messages: &[Arc<Message>]) -> io::Result<()> {
+        for message in messages {
+            self.log.append(message)?;
+        }
+        self.log.flush()
+    }
+
+    // Write the acknowledgements, rewriting the log once it's mostly
+    // acknowledgements
+    fn sync(&mut self, queues: &Queues) -> io::Result<()> {
+        for id in queues.take_acked(&self.name) {
+            self.log.ack(&id)?;
+        }
+        if self.log.should_compact(queues.pending(&self.name)) {
+            let messages = queues.messages(&self.name);
+            self.log.compact(messages.iter())?;
+        }
+        self.log.flush()
+    }
+}
+
+// Adds published messages to the durable subscriptions they match and
+// delivers again what wasn't acknowledged in time. Owns the logs, so
+// consumers never wait on the disk.
+pub struct Durables {
+    receiver: ReactiveSignalReceiver<Arc<Message>>,
+    ticker: ReactiveSignalReceiver<()>,
+    queues: Queues,
+    writers: Vec<Writer>,
+}
+
+impl Durables {
+    pub fn new(receiver: SignalReceiver<Arc<Message>>, configs: &[DurableConfig], dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+
+        let mut state = State::default();
+        let mut writers = Vec::new();
+        for config in configs {
+            let (writer, messages) = Writer::open(config, dir).map_err(|reason| {
+                let reason = format!("invalid durable subscription \"{}\": {}", config.name, reason);
+                io::Error::new(io::ErrorKind::InvalidInput, reason)
+            })?;
+            state.durables.insert(config.name.clone(), Durable::new(config, messages));
+            writers.push(writer);
+        }
+
+        Ok(Self {
+            receiver: ReactiveSignalReceiver::new(receiver)?,
+            ticker: ticker(Duration::from_secs(1))?,
+            queues: Queues {
+                state: Arc::new(Mutex::new(state)),
+            },
+            writers,
+        })
+    }
+
+    pub fn queues(&self) -> Queues {
+        self.queues.clone()
+    }
+
+    fn add(&mut self, messages: &[Arc<Message>]) {
+        for writer in self.writers.iter_mut() {
+            let matched = messages
+                .iter()
+                .filter(|message| writer.matches(message))
+                .cloned()
+                .collect::<Vec<_>>();
+            if matched.is_empty() {
+                continue;
+            }
+
+            // Only what is on disk is queued, so nothing is delivered that could be lost
+            match writer.append(&matched) {
+                Ok(()) => self.queues.add(&writer.name, matched),
+                Err(e) => error!(
+                    "Failed to write durable subscription \"{}\", dropping {} messages: {:?}",
+                    writer.name,
+                    matched.len(),
+                    e
+                ),
+            }
+        }
+    }
+
+    fn tick(&mut self) {
+        self.queues.deliver();
+        for writer in self.writers.iter_mut() {
+            if let Err(e) = writer.sync(&self.queues) {
+                error!("Failed to write durable subscription \"{}\": {:?}", writer.name, e);
+            }
+        }
+    }
+}
+
+impl Reactor for Durables {
+    type Input = ();
+    type Output = ();
+
+    fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
+        match reaction {
+            Reaction::Event(event) => {
+                if event.token() == self.ticker.token() {
+                    while let Ok(()) = self.ticker.try_recv() {}
+                    self.tick();
+                    return Reaction::Continue;
+                }
+
+                if event.token() == self.receiver.token() {
+                    let mut messages = Vec::new();
+                    while let Ok(message) = self.receiver.try_recv() {
+                        messages.push(message);
+                    }
+                    self.add(&messages);
+                    return Reaction::Continue;
+                }
+
+                event.into()
+            }
+            Reaction::Value(()) | Reaction::Continue => Reaction::Continue,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{MessageType, Severity};
+
+    fn message(id: u64) -> Arc<Message> {
+        let mut message = Message::new("web1", "down".into(), MessageType::Error, Severity::Critical);
+        message.stamp(format!("1-{:x}", id), id, 0);
+        Arc::new(message)
+    }
+
+    #[test]
+    fn acknowledges_and_drops_by_id() {
+        let config = DurableConfig {
+            name: "tickets".into(),
+            channels: Vec::new(),
+            filter: None,
+            ack_timeout: 30,
+            max_in_flight: 10,
+            max_messages: 3,
+        };
+        let mut durable = Durable::new(&config, (1..=2).map(message).collect());
+        assert!(!durable.add(message(3)));
+        assert!(durable.add(message(4)));
+        assert!(!durable.add(message(5)));
+
+        durable.ack("1-4");
+        durable.ack("1-4");
+        durable.ack("1-9");
+        let ids = durable.pending.values().map(|p| p.message.id().unwrap()).collect::<Vec<_>>();
+        assert_eq!(ids, vec!["1-3", "1-5"]);
+        assert_eq!(durable.acked, vec!["1-1", "1-2", "1-4"]);
+        assert_eq!(durable.keys.len(), 2);
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::{HashSet, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::messages::Message;
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum EntryRef<'a> {
+    Message(&'a Message),
+    Ack(&'a str),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Entry {
+    Message(Box<Message>),
+    Ack(String),
+}
+
+// The messages of a durable subscription and their acknowledgements, as
+// newline separated json appended to a file. Rewritten with only the
+// unacknowledged messages once acknowledgements make up most of it.
+pub struct Log {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    acks: usize,
+}
+
+impl Log {
+    // Opens the log, returning the messages not acknowledged yet
+    pub fn open(path: PathBuf) -> io::Result<(Log, VecDeque<Arc<Message>>)> {
+        let mut messages = Vec::new();
+        let mut acked = HashSet::new();
+        match File::open(&path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    // A partly written last line is skipped
+                    match serde_json::from_str::<Entry>(&line?) {
+                        Ok(Entry::Message(message)) => messages.push(*message),
+                        Ok(Entry::Ack(id)) => {
+                            acked.insert(id);
+                        }
+                        Err(_) => continue,
+                    }
+                }
+            }
+            Err(ref e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        let messages = messages
+            .into_iter()
+            .filter(|m| m.id().is_some_and(|id| !acked.contains(id)))
+            .map(Arc::new)
+            .collect::<VecDeque<_>>();
+
+        let log = Log {
+            writer: rewrite(&path, messages.iter())?,
+            path,
+            acks: 0,
+        };
+        Ok((log, messages))
+    }
+
+    pub fn append(&mut self, message: &Message) -> io::Result<()> {
+        self.write(&EntryRef::Message(message))
+    }
+
+    pub fn ack(&mut self, id: &str) -> io::Result<()> {
+        self.acks += 1;
+        self.write(&EntryRef::Ack(id))
+    }
+
+    fn write(&mut self, entry: &EntryRef) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, entry)?;
+        self.writer.write_all(b"\n")
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn should_compact(&self, pending: usize) -> bool {
+        self.acks > 1000 && self.acks > pending
+    }
+
+    pub fn compact<'a, I>(&mut self, messages: I) -> io::Result<()>
+    where
+        I: Iterator<Item = &'a Arc<Message>>,
+    {
+        self.writer.flush()?;
+        self.writer = rewrite(&self.path, messages)?;
+        self.acks = 0;
+        Ok(())
+    }
+}
+
+// Replace the file with only `messages`, returning a writer appending to it
+fn rewrite<'a, I>(path: &Path, messages: I) -> io::Result<BufWriter<File>>
+where
+    I: Iterator<Item = &'a Arc<Message>>,
+{
+    let tmp = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    for message in messages {
+        serde_json::to_writer(&mut writer, &EntryRef::Message(message))?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    fs::rename(&tmp, path)?;
+
+    let file = OpenOptions::new().append(true).open(path)?;
+    Ok(BufWriter::new(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{MessageType, Severity};
+    use std::env;
+    use std::process;
+
+    fn message(id: u64) -> Message {
+        let mut message = Message::new("web1", "down".into(), MessageType::Error, Severity::Critical);
+        message.stamp(format!("1-{:x}", id), id, 0);
+        message
+    }
+
+    fn ids(messages: &VecDeque<Arc<Message>>) -> Vec<&str> {
+        messages.iter().filter_map(|m| m.id()).collect()
+    }
+
+    #[test]
+    fn reloads_unacknowledged_messages() {
+        let dir = env::temp_dir().join(format!("remonitor-log-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tickets.jsonl");
+        let _ = fs::remove_file(&path);
+
+        let (mut log, messages) = Log::open(path.clone()).unwrap();
+        assert!(messages.is_empty());
+        for id in 1..=4 {
+            log.append(&message(id)).unwrap();
+        }
+        log.ack("1-2").unwrap();
+        log.ack("1-3").unwrap();
+        log.flush().unwrap();
+        drop(log);
+
+        // A partly written last line is skipped
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"message\": {\"payl").unwrap();
+
+        let (mut log, messages) = Log::open(path.clone()).unwrap();
+        assert_eq!(ids(&messages), vec!["1-1", "1-4"]);
+
+        // Opening rewrote it with just those
+        log.ack("1-1").unwrap();
+        log.flush().unwrap();
+        let lines = fs::read_to_string(&path).unwrap().lines().count();
+        assert_eq!(lines, 3);
+
+        let (log, messages) = Log::open(path.clone()).unwrap();
+        assert_eq!(ids(&messages), vec!["1-4"]);
+        drop(log);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,465 @@
+use std::cmp::Ordering;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use regex::Regex;
+use serde_json::Value;
+
+use crate::messages::Message;
+
+// Filters come from clients, these keep parsing and evaluating them, both
+// recursive, from running out of stack
+const MAX_LENGTH: usize = 4096;
+const MAX_DEPTH: usize = 32;
+
+// Filter expressions evaluated against messages, e.g.
+//
+// payload.cpu > 90 && labels.env == "prod"
+// !(severity == "debug") || channel == "db1"
+// payload =~ "timeout|refused"
+//
+// Paths are resolved with `Message::lookup`, a path that doesn't exist is `null`.
+// Ordering comparisons only hold between two numbers or two strings,
+// `=~` matches a string against a regular expression.
+#[derive(Debug)]
+pub struct Filter {
+    expr: Expr,
+}
+
+impl Filter {
+    pub fn parse(source: &str) -> Result<Filter, String> {
+        if source.len() > MAX_LENGTH {
+            return Err(format!("filter is longer than {} bytes", MAX_LENGTH));
+        }
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, pos: 0, depth: 0 };
+        let expr = parser.or()?;
+        match parser.peek() {
+            None => Ok(Filter { expr }),
+            Some(token) => Err(format!("unexpected {:?} in filter", token)),
+        }
+    }
+
+    pub fn matches(&self, message: &Message) -> bool {
+        self.expr.eval(message)
+    }
+}
+
+// Channel patterns, where `*` matches any number of characters
+pub fn glob(pattern: &str, s: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    if !s.starts_with(first) {
+        return false;
+    }
+
+    let mut rest = &s[first.len()..];
+    let mut parts = parts.collect::<Vec<_>>();
+    let last = match parts.pop() {
+        Some(last) => last,
+        None => return rest.is_empty(),
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+#[derive(Debug)]
+enum Operand {
+    Literal(Value),
+    Path(Vec<String>),
+}
+
+#[derive(Debug)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Operand, Op, Operand),
+    Matches(Operand, Regex),
+    Truthy(Operand),
+}
+
+impl Operand {
+    fn resolve(&self, message: &Message) -> Value {
+        match self {
+            Operand::Literal(value) => value.clone(),
+            Operand::Path(path) => message.lookup(path),
+        }
+    }
+}
+
+fn ordering(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Number(l), Value::Number(r)) => l.as_f64()?.partial_cmp(&r.as_f64()?),
+        (Value::String(l), Value::String(r)) => Some(l.cmp(r)),
+        _ => None,
+    }
+}
+
+fn equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Number(_), Value::Number(_)) => ordering(left, right) == Some(Ordering::Equal),
+        _ => left == right,
+    }
+}
+
+impl Expr {
+    fn eval(&self, message: &Message) -> bool {
+        match self {
+            Expr::Or(l, r) => l.eval(message) || r.eval(message),
+            Expr::And(l, r) => l.eval(message) && r.eval(message),
+            Expr::Not(e) => !e.eval(message),
+            Expr::Compare(l, op, r) => {
+                let (l, r) = (l.resolve(message), r.resolve(message));
+                match op {
+                    Op::Eq => equal(&l, &r),
+                    Op::Ne => !equal(&l, &r),
+                    Op::Gt => ordering(&l, &r) == Some(Ordering::Greater),
+                    Op::Ge => ordering(&l, &r).is_some_and(|o| o != Ordering::Less),
+                    Op::Lt => ordering(&l, &r) == Some(Ordering::Less),
+                    Op::Le => ordering(&l, &r).is_some_and(|o| o != Ordering::Greater),
+                }
+            }
+            Expr::Matches(operand, regex) => match operand.resolve(message) {
+                Value::String(s) => regex.is_match(&s),
+                _ => false,
+            },
+            Expr::Truthy(operand) => !matches!(operand.resolve(message), Value::Null | Value::Bool(false)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Literal(Value),
+    Dot,
+    Op(Op),
+    Match,
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        let token = match c {
+            ' ' | '\t' | '\r' | '\n' => {
+                chars.next();
+                continue;
+            }
+            '.' => single(&mut chars, Token::Dot),
+            '(' => single(&mut chars, Token::Open),
+            ')' => single(&mut chars, Token::Close),
+            '&' => pair(&mut chars, '&', Token::And, None)?,
+            '|' => pair(&mut chars, '|', Token::Or, None)?,
+            '=' => match pair(&mut chars, '=', Token::Op(Op::Eq), Some(Token::Match))? {
+                Token::Match if chars.next() != Some('~') => return Err("expected '==' or '=~' in filter".into()),
+                token => token,
+            },
+            '!' => pair(&mut chars, '=', Token::Op(Op::Ne), Some(Token::Not))?,
+            '>' => pair(&mut chars, '=', Token::Op(Op::Ge), Some(Token::Op(Op::Gt)))?,
+            '<' => pair(&mut chars, '=', Token::Op(Op::Le), Some(Token::Op(Op::Lt)))?,
+            '"' | '\'' => Token::Literal(Value::String(string(&mut chars)?)),
+            // An array index in a path, such as `payload.items.0.name`
+            '0'..='9' if tokens.last() == Some(&Token::Dot) => Token::Ident(index(&mut chars)),
+            '-' | '0'..='9' => Token::Literal(number(&mut chars)?),
+            c if c.is_alphabetic() || c == '_' => {
+                let mut ident = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_' || c == '-') {
+                        break;
+                    }
+                    ident.push(c);
+                    chars.next();
+                }
+                match ident.as_str() {
+                    "true" => Token::Literal(Value::Bool(true)),
+                    "false" => Token::Literal(Value::Bool(false)),
+                    "null" => Token::Literal(Value::Null),
+                    _ => Token::Ident(ident),
+                }
+            }
+            c => return Err(format!("unexpected character '{}' in filter", c)),
+        };
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+fn single(chars: &mut Peekable<Chars>, token: Token) -> Token {
+    chars.next();
+    token
+}
+
+// Two character operators, or a one character operator if `short` is given
+fn pair(chars: &mut Peekable<Chars>, second: char, long: Token, short: Option<Token>) -> Result<Token, String> {
+    let first = chars.next().unwrap_or_default();
+    if chars.peek() == Some(&second) {
+        chars.next();
+        return Ok(long);
+    }
+    short.ok_or_else(|| format!("expected '{}{}' in filter", first, second))
+}
+
+fn string(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    let quote = chars.next().unwrap_or_default();
+    let mut s = String::new();
+    loop {
+        match chars.next() {
+            Some('\\') => match chars.next() {
+                Some(c) => s.push(c),
+                None => break,
+            },
+            Some(c) if c == quote => return Ok(s),
+            Some(c) => s.push(c),
+            None => break,
+        }
+    }
+    Err("unterminated string in filter".into())
+}
+
+fn index(chars: &mut Peekable<Chars>) -> String {
+    let mut index = String::new();
+    while let Some(&c) = chars.peek() {
+        if !c.is_ascii_digit() {
+            break;
+        }
+        index.push(c);
+        chars.next();
+    }
+    index
+}
+
+fn number(chars: &mut Peekable<Chars>) -> Result<Value, String> {
+    let mut n = String::new();
+    while let Some(&c) = chars.peek() {
+        if !(c.is_ascii_digit() || c == '-' || c == '+' || c == '.' || c == 'e' || c == 'E') {
+            break;
+        }
+        n.push(c);
+        chars.next();
+    }
+    serde_json::from_str::<serde_json::Number>(&n)
+        .map(Value::Number)
+        .map_err(|_| format!("invalid number \"{}\" in filter", n))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    // Parentheses and negations currently open
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some(Token::Not) | Some(Token::Open) => {
+                self.depth += 1;
+                if self.depth > MAX_DEPTH {
+                    return Err("filter nested too deeply".into());
+                }
+                let expr = self.nested();
+                self.depth -= 1;
+                expr
+            }
+            _ => self.comparison(),
+        }
+    }
+
+    fn nested(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Not) => Ok(Expr::Not(Box::new(self.unary()?))),
+            _ => {
+                let expr = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err("expected ')' in filter".into()),
+                }
+            }
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let left = self.operand()?;
+        match self.peek() {
+            Some(&Token::Op(op)) => {
+                self.next();
+                Ok(Expr::Compare(left, op, self.operand()?))
+            }
+            Some(Token::Match) => {
+                self.next();
+                match self.next() {
+                    Some(Token::Literal(Value::String(pattern))) => Regex::new(&pattern)
+                        .map(|regex| Expr::Matches(left, regex))
+                        .map_err(|e| format!("invalid pattern in filter: {}", e)),
+                    _ => Err("expected a string after '=~' in filter".into()),
+                }
+            }
+            _ => Ok(Expr::Truthy(left)),
+        }
+    }
+
+    fn operand(&mut self) -> Result<Operand, String> {
+        match self.next() {
+            Some(Token::Literal(value)) => Ok(Operand::Literal(value)),
+            Some(Token::Ident(ident)) => {
+                let mut path = vec![ident];
+                while self.peek() == Some(&Token::Dot) {
+                    self.next();
+                    match self.next() {
+                        Some(Token::Ident(ident)) => path.push(ident),
+                        _ => return Err("expected a field name after '.' in filter".into()),
+                    }
+                }
+                Ok(Operand::Path(path))
+            }
+            Some(token) => Err(format!("unexpected {:?} in filter", token)),
+            None => Err("unexpected end of filter".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::messages::{MessageType, Severity};
+
+    fn message(payload: Value) -> Message {
+        Message::new("web1", payload.into(), MessageType::Status, Severity::Warning)
+    }
+
+    fn matches(filter: &str, payload: Value) -> bool {
+        Filter::parse(filter).unwrap().matches(&message(payload))
+    }
+
+    #[test]
+    fn compares_paths_with_literals() {
+        let payload = json!({"cpu": 95, "host": "web1"});
+        assert!(matches("payload.cpu > 90", payload.clone()));
+        assert!(matches("payload.cpu >= 95 && payload.host == \"web1\"", payload.clone()));
+        assert!(!matches("payload.cpu < 90", payload.clone()));
+        assert!(matches("payload.cpu == 95.0", payload.clone()));
+        assert!(matches("payload.missing == null", payload));
+    }
+
+    #[test]
+    fn resolves_message_fields() {
+        assert!(matches("channel == \"web1\" && severity == \"warning\"", json!({})));
+        assert!(matches("message_type != \"error\"", json!({})));
+    }
+
+    #[test]
+    fn combines_with_precedence() {
+        let payload = json!({"a": 1, "b": 2});
+        assert!(matches("payload.a == 2 || payload.a == 1 && payload.b == 2", payload.clone()));
+        assert!(!matches("(payload.a == 2 || payload.a == 1) && payload.b == 3", payload.clone()));
+        assert!(matches("!(payload.a == 2)", payload));
+    }
+
+    #[test]
+    fn indexes_arrays() {
+        let payload = json!({"items": [{"name": "disk"}, {"name": "cpu"}]});
+        assert!(matches("payload.items.1.name == \"cpu\"", payload.clone()));
+        assert!(matches("payload.items.0.name == \"disk\"", payload.clone()));
+        assert!(!matches("payload.items.2.name", payload));
+    }
+
+    #[test]
+    fn matches_regular_expressions() {
+        assert!(matches("payload.error =~ \"timeout|refused\"", json!({"error": "connection refused"})));
+        assert!(!matches("payload.error =~ \"timeout\"", json!({"error": 5})));
+    }
+
+    #[test]
+    fn orders_only_numbers_and_strings() {
+        assert!(!matches("payload.a > 1", json!({"a": "2"})));
+        assert!(matches("payload.a > \"1\"", json!({"a": "2"})));
+        assert!(matches("payload.a > -1.5e1", json!({"a": -2})));
+    }
+
+    #[test]
+    fn rejects_invalid_filters() {
+        for filter in &["payload.a >", "payload.a = 1", "(payload.a", "payload.a == \"x", "payload. == 1", "a =~ 1", "a =~ \"(\"", "a b", "#"] {
+            assert!(Filter::parse(filter).is_err(), "{}", filter);
+        }
+    }
+
+    #[test]
+    fn limits_nesting_and_length() {
+        let nested = format!("{}payload.a == 1{}", "(".repeat(MAX_DEPTH), ")".repeat(MAX_DEPTH));
+        assert!(matches(&nested, json!({"a": 1})));
+        assert!(matches(&format!("{}payload.a", "!".repeat(MAX_DEPTH)), json!({"a": 1})));
+
+        let too_deep = format!("{}payload.a == 1{}", "(".repeat(MAX_DEPTH + 1), ")".repeat(MAX_DEPTH + 1));
+        assert_eq!(Filter::parse(&too_deep).unwrap_err(), "filter nested too deeply");
+        assert_eq!(Filter::parse(&"(!".repeat(100_000)).unwrap_err(), "filter is longer than 4096 bytes");
+        assert_eq!(Filter::parse(&"(".repeat(MAX_LENGTH)).unwrap_err(), "filter nested too deeply");
+
+        // Long chains stay within the length limit
+        let chain = vec!["payload.a"; 300].join(" && ");
+        assert!(matches(&chain, json!({"a": 1})));
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob("web*", "web1"));
+        assert!(glob("*", ""));
+        assert!(glob("a*b*c", "aXbYc"));
+        assert!(!glob("a*b*c", "aXcYb"));
+        assert!(glob("web1", "web1"));
+        assert!(!glob("web1", "web12"));
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use crate::messages::Message;
+use crate::timer::now_millis;
+
+// The position of a message in everything published, from its id.
+// Ids are `{epoch}-{position}` in hex, see `Publisher`.
+pub fn position(id: &str) -> Option<(&str, u64)> {
+    let mut parts = id.splitn(2, '-');
+    let epoch = parts.next()?;
+    let position = u64::from_str_radix(parts.next()?, 16).ok()?;
+    Some((epoch, position))
+}
+
+// Messages missed since a position
+pub struct Missed {
+    pub messages: Vec<Arc<Message>>,
+    // Whether older messages were missed too, no longer kept
+    pub complete: bool,
+    // The position of the last message, to skip when delivered live as well
+    pub through: u64,
+}
+
+// The sequences of a channel since the server started
+#[derive(Default)]
+struct Sequences {
+    // Of the newest message published
+    latest: u64,
+    // Of the newest message no longer kept
+    dropped: u64,
+}
+
+struct Kept {
+    epoch: String,
+    messages: VecDeque<(u64, Arc<Message>)>,
+    channels: HashMap<String, Sequences>,
+}
+
+// Recently published messages, kept for `retention` or until there are
+// more than `max_messages`, for clients resuming a session.
+#[derive(Clone)]
+pub struct History {
+    retention: Duration,
+    max_messages: usize,
+    kept: Arc<Mutex<Kept>>,
+}
+
+impl History {
+    pub fn new(retention: Duration, max_messages: usize) -> Self {
+        let kept = Kept {
+            epoch: String::new(),
+            messages: VecDeque::new(),
+            channels: HashMap::new(),
+        };
+        Self {
+            retention,
+            max_messages,
+            kept: Arc::new(Mutex::new(kept)),
+        }
+    }
+
+    fn kept(&self) -> MutexGuard<'_, Kept> {
+        match self.kept.lock() {
+            Ok(kept) => kept,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_messages > 0 && self.retention > Duration::from_secs(0)
+    }
+
+    // Called with stamped messages, in the order they are published
+    pub fn push(&self, message: Arc<Message>) {
+        if !self.is_enabled() {
+            return;
+        }
+        let (epoch, position) = match message.id().and_then(position) {
+            Some((epoch, position)) => (epoch.to_owned(), position),
+            None => return,
+        };
+
+        let oldest = now_millis().saturating_sub(self.retention.as_secs().saturating_mul(1000));
+        let mut kept = self.kept();
+        kept.epoch = epoch;
+        let sequence = message.sequence().unwrap_or(0);
+        kept.channels.entry(message.channel().to_owned()).or_default().latest = sequence;
+        kept.messages.push_back((position, message));
+        while kept.messages.len() > self.max_messages
+            || kept.messages.front().is_some_and(|(_, m)| m.received_at().unwrap_or(0) < oldest)
+        {
+            if let Some((_, dropped)) = kept.messages.pop_front() {
+                if let Some(channel) = kept.channels.get_mut(dropped.channel()) {
+                    channel.dropped = dropped.sequence().unwrap_or(0);
+                }
+            }
+        }
+    }
+
+    // The messages published after the one with `last_id`
+    pub fn since(&self, last_id: &str) -> Result<Missed, String> {
+        let (epoch, last) = position(last_id).ok_or("Invalid last_id")?;
+        let kept = self.kept();
+        if epoch != kept.epoch {
+            return Err("Unknown last_id, the server has restarted".into());
+        }
+
+        let messages = kept
+            .messages
+            .iter()
+            .filter(|(position, _)| *position > last)
+            .map(|(_, message)| message.clone())
+            .collect();
+        let complete = kept.messages.front().is_none_or(|(oldest, _)| *oldest <= last + 1);
+        let through = kept.messages.back().map_or(last, |(newest, _)| last.max(*newest));
+        Ok(Missed { messages, complete, through })
+    }
+
+    // The messages published after the `sequence` of each channel, and all
+    // those kept of other channels
+    pub fn since_sequences(&self, sequences: &HashMap<String, u64>) -> Result<Missed, String> {
+        let kept = self.kept();
+        // Sequences start over when the server restarts
+        for (channel, last) in sequences {
+            if *last > kept.channels.get(channel).map_or(0, |c| c.latest) {
+                return Err(format!("Unknown sequence for \"{}\", the server has restarted", channel));
+            }
+        }
+
+        let last = |message: &Message| sequences.get(message.channel()).copied().unwrap_or(0);
+        let messages = kept
+            .messages
+            .iter()
+            .filter(|(_, message)| message.sequence().unwrap_or(0) > last(message))
+            .map(|(_, message)| message.clone())
+            .collect();
+        let complete = sequences
+            .iter()
+            .all(|(channel, last)| kept.channels.get(channel).is_none_or(|c| c.dropped <= *last));
+        let through = kept.messages.back().map_or(0, |(newest, _)| *newest);
+        Ok(Missed { messages, complete, through })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{MessageType, Severity};
+
+    fn message(position: u64) -> Arc<Message> {
+        stamped("web1", position, position)
+    }
+
+    fn stamped(channel: &str, position: u64, sequence: u64) -> Arc<Message> {
+        let mut message = Message::new(channel, "up".into(), MessageType::Status, Severity::Info);
+        message.stamp(format!("16bb-{:x}", position), sequence, now_millis());
+        Arc::new(message)
+    }
+
+    fn positions(missed: &Missed) -> Vec<u64> {
+        missed.messages.iter().filter_map(|m| m.id().and_then(position)).map(|(_, p)| p).collect()
+    }
+
+    #[test]
+    fn reads_positions_from_ids() {
+        assert_eq!(position("16bb3a2c1f0-2a"), Some(("16bb3a2c1f0", 42)));
+        assert_eq!(position("16bb3a2c1f0"), None);
+        assert_eq!(position("16bb3a2c1f0-xyz"), None);
+    }
+
+    #[test]
+    fn returns_the_messages_since_an_id() {
+        let history = History::new(Duration::from_secs(60), 3);
+        for position in 1..=5 {
+            history.push(message(position));
+        }
+
+        let missed = history.since("16bb-3").unwrap();
+        assert_eq!((positions(&missed), missed.complete, missed.through), (vec![4, 5], true, 5));
+
+        // 1 is no longer kept
+        let missed = history.since("16bb-1").unwrap();
+        assert_eq!((positions(&missed), missed.complete, missed.through), (vec![3, 4, 5], false, 5));
+
+        let missed = history.since("16bb-5").unwrap();
+        assert_eq!((positions(&missed), missed.complete, missed.through), (vec![], true, 5));
+
+        assert!(history.since("16bc-1").is_err());
+        assert!(history.since("nonsense").is_err());
+    }
+
+    #[test]
+    fn returns_the_messages_since_sequences() {
+        let history = History::new(Duration::from_secs(60), 4);
+        // web1 1 to 3 and db1 1 to 3, taking turns
+        for position in 1..=6 {
+            let channel = if position % 2 == 1 { "web1" } else { "db1" };
+            history.push(stamped(channel, position, position.div_ceil(2)));
+        }
+        let sequences = |pairs: &[(&str, u64)]| pairs.iter().map(|(c, s)| (c.to_string(), *s)).collect::<HashMap<_, _>>();
+
+        let missed = history.since_sequences(&sequences(&[("web1", 2), ("db1", 2)])).unwrap();
+        assert_eq!((positions(&missed), missed.complete, missed.through), (vec![5, 6], true, 6));
+
+        // Other channels are replayed from the oldest kept
+        let missed = history.since_sequences(&sequences(&[("web1", 3)])).unwrap();
+        assert_eq!((positions(&missed), missed.complete), (vec![4, 6], true));
+
+        // web1 1 and db1 1 are no longer kept
+        let missed = history.since_sequences(&sequences(&[("web1", 0), ("db1", 1)])).unwrap();
+        assert_eq!((positions(&missed), missed.complete), (vec![3, 4, 5, 6], false));
+
+        assert!(history.since_sequences(&sequences(&[("web1", 4)])).is_err());
+        assert!(history.since_sequences(&sequences(&[("mail", 1)])).is_err());
+    }
+}
@@ -5,4 +5,17 @@ mod messages;
 mod clients;
 mod auth;
 mod throttle;
+mod compression;
+mod publisher;
+mod presence;
+mod history;
+mod router;
+mod alerts;
+mod sinks;
+mod aggregates;
+mod rollups;
+mod durable;
+mod filter;
+mod ratelimit;
+mod timer;
 pub mod config;
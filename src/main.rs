@@ -12,7 +12,7 @@ struct Opts {
     arg_config: Option<String>,
 }
 
-const USAGE: &'static str = "
+const USAGE: &str = "
 Remonitor
 
 Usage:
@@ -24,14 +24,10 @@ Options:
 ";
 
 fn print_err(e: Error, file_path: &str) {
-    match e {
-        Error::Io(io_err) => {
-            match io_err.kind() {
-                ErrorKind::NotFound => eprintln!("The file \"{}\" can not be found", file_path),
-                _ => {}
-            }
+    if let Error::Io(io_err) = e {
+        if io_err.kind() == ErrorKind::NotFound {
+            eprintln!("The file \"{}\" can not be found", file_path);
         }
-        _ => {}
     }
 }
 
@@ -68,5 +64,8 @@ Remonitor server
     }
 
     println!("{}", msg);
-    server::serve(config);
+    if let Err(e) = server::serve(config) {
+        eprintln!("{:?}", e);
+        std::process::exit(1)
+    }
 }
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// What a monitor tells about itself after authenticating
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Metadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub tags: HashMap<String, String>,
+}
+
+impl Metadata {
+    pub fn parse(payload: Value) -> Result<Metadata, String> {
+        serde_json::from_value(payload).map_err(|e| format!("Invalid metadata: {}", e))
+    }
+
+    // A tag, or one of the named fields, `env` being short for environment
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        let field = match key {
+            "hostname" => &self.hostname,
+            "service" => &self.service,
+            "version" => &self.version,
+            "env" | "environment" => &self.environment,
+            _ => return self.tags.get(key).map(String::as_str),
+        };
+        field.as_ref().map(String::as_str)
+    }
+}
+
+// Tags that must all match, written as `env=prod,service=api`
+#[derive(Debug, Clone)]
+pub struct Selector {
+    tags: Vec<(String, String)>,
+}
+
+impl Selector {
+    pub fn parse(selector: &str) -> Result<Selector, String> {
+        let mut tags = Vec::new();
+        for pair in selector.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let mut parts = pair.splitn(2, '=');
+            match (parts.next().map(str::trim), parts.next().map(str::trim)) {
+                (Some(key), Some(value)) if !key.is_empty() => tags.push((key.to_owned(), value.to_owned())),
+                _ => return Err(format!("Invalid selector \"{}\", expected key=value", pair)),
+            }
+        }
+        if tags.is_empty() {
+            return Err("Empty selector".into());
+        }
+        Ok(Selector { tags })
+    }
+
+    pub fn matches(&self, metadata: Option<&Metadata>) -> bool {
+        let metadata = match metadata {
+            Some(metadata) => metadata,
+            None => return false,
+        };
+        self.tags.iter().all(|(key, value)| metadata.tag(key) == Some(value.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parses_selectors() {
+        let selector = Selector::parse(" env = prod,service=api,").unwrap();
+        assert_eq!(
+            selector.tags,
+            vec![("env".to_owned(), "prod".to_owned()), ("service".to_owned(), "api".to_owned())]
+        );
+        let selector = Selector::parse("url=http://a?b=c").unwrap();
+        assert_eq!(selector.tags, vec![("url".to_owned(), "http://a?b=c".to_owned())]);
+
+        assert!(Selector::parse("").is_err());
+        assert!(Selector::parse(" , ").is_err());
+        assert!(Selector::parse("env").is_err());
+        assert!(Selector::parse("=prod").is_err());
+    }
+
+    #[test]
+    fn matches_fields_and_tags() {
+        let payload = json!({ "service": "api", "environment": "prod", "tags": { "region": "eu" } });
+        let metadata = Metadata::parse(payload).unwrap();
+
+        assert!(Selector::parse("env=prod,service=api").unwrap().matches(Some(&metadata)));
+        assert!(Selector::parse("environment=prod,region=eu").unwrap().matches(Some(&metadata)));
+        assert!(!Selector::parse("env=prod,region=us").unwrap().matches(Some(&metadata)));
+        assert!(!Selector::parse("hostname=web1").unwrap().matches(Some(&metadata)));
+        assert!(!Selector::parse("env=prod").unwrap().matches(None));
+        assert!(Metadata::parse(json!({ "tags": ["eu"] })).is_err());
+    }
+}
@@ -0,0 +1,150 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{MessageType, Payload};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Bucket {
+    pub le: f64,
+    pub count: u64,
+}
+
+// The json payload of a gauge, counter or histogram message:
+//
+// {"name": "cpu", "value": 93.5, "unit": "percent", "labels": {"env": "prod"}}
+//
+// Histograms have cumulative buckets, a sum and a count instead of a value:
+//
+// {"name": "latency", "unit": "s", "buckets": [{"le": 0.1, "count": 3}, {"le": 1.0, "count": 9}], "sum": 2.4, "count": 10}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Metric {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub labels: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub buckets: Vec<Bucket>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sum: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub count: Option<u64>,
+}
+
+impl Metric {
+    pub fn parse(payload: &Payload, message_type: MessageType) -> Result<Metric, String> {
+        let metric: Metric = match payload {
+            Payload::Json(value) => serde_json::from_value(value.clone()),
+            Payload::Text(text) => serde_json::from_str(text),
+            Payload::Binary(_) => return Err("metric payload must be json".into()),
+        }
+        .map_err(|e| format!("invalid metric: {}", e))?;
+
+        metric.validate(message_type)?;
+        Ok(metric)
+    }
+
+    fn validate(&self, message_type: MessageType) -> Result<(), String> {
+        if self.name.is_empty() {
+            return Err("metric name is empty".into());
+        }
+
+        match message_type {
+            MessageType::Gauge => self.finite_value().map(|_| ()),
+            MessageType::Counter => match self.finite_value()? {
+                v if v < 0.0 => Err(format!("counter \"{}\" is negative", self.name)),
+                _ => Ok(()),
+            },
+            MessageType::Histogram => self.validate_histogram(),
+            _ => Err("not a metric message type".into()),
+        }
+    }
+
+    fn finite_value(&self) -> Result<f64, String> {
+        match self.value {
+            Some(v) if v.is_finite() => Ok(v),
+            Some(_) => Err(format!("metric \"{}\" value is not a finite number", self.name)),
+            None => Err(format!("metric \"{}\" has no value", self.name)),
+        }
+    }
+
+    fn validate_histogram(&self) -> Result<(), String> {
+        if self.buckets.is_empty() {
+            return Err(format!("histogram \"{}\" has no buckets", self.name));
+        }
+
+        for pair in self.buckets.windows(2) {
+            if pair[0].le.partial_cmp(&pair[1].le) != Some(Ordering::Less) {
+                return Err(format!("histogram \"{}\" buckets are not in ascending order", self.name));
+            }
+            if pair[0].count > pair[1].count {
+                return Err(format!("histogram \"{}\" bucket counts are not cumulative", self.name));
+            }
+        }
+
+        match (self.sum, self.count) {
+            (Some(sum), Some(count)) if sum.is_finite() => {
+                let last = self.buckets.last().map(|b| b.count).unwrap_or(0);
+                if count < last {
+                    return Err(format!("histogram \"{}\" count is less than its buckets", self.name));
+                }
+                Ok(())
+            }
+            _ => Err(format!("histogram \"{}\" needs a finite sum and a count", self.name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse(payload: serde_json::Value, message_type: MessageType) -> Result<Metric, String> {
+        Metric::parse(&payload.into(), message_type)
+    }
+
+    #[test]
+    fn parses_gauges_and_counters() {
+        let gauge = parse(json!({"name": "cpu", "value": 93.5, "unit": "percent", "labels": {"env": "prod"}}), MessageType::Gauge).unwrap();
+        assert_eq!(gauge.value, Some(93.5));
+        assert_eq!(gauge.labels["env"], "prod");
+
+        let text = Payload::Text(r#"{"name": "requests", "value": 12}"#.into());
+        assert!(Metric::parse(&text, MessageType::Counter).is_ok());
+        assert!(Metric::parse(&Payload::Binary(vec![1, 2]), MessageType::Gauge).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        assert!(parse(json!({"name": "", "value": 1.0}), MessageType::Gauge).is_err());
+        assert!(parse(json!({"name": "cpu"}), MessageType::Gauge).is_err());
+        assert!(parse(json!({"name": "cpu", "value": "high"}), MessageType::Gauge).is_err());
+        assert!(parse(json!({"name": "requests", "value": -1.0}), MessageType::Counter).is_err());
+        assert!(parse(json!({"name": "cpu", "value": 1.0}), MessageType::Status).is_err());
+    }
+
+    #[test]
+    fn validates_histograms() {
+        let buckets = json!([{"le": 0.1, "count": 3}, {"le": 1.0, "count": 9}]);
+        let histogram = json!({"name": "latency", "buckets": buckets, "sum": 2.4, "count": 10});
+        assert_eq!(parse(histogram, MessageType::Histogram).unwrap().buckets.len(), 2);
+
+        let unordered = json!([{"le": 1.0, "count": 3}, {"le": 0.1, "count": 9}]);
+        let not_cumulative = json!([{"le": 0.1, "count": 9}, {"le": 1.0, "count": 3}]);
+        let invalid = [
+            json!({"name": "latency", "buckets": [], "sum": 0.0, "count": 0}),
+            json!({"name": "latency", "buckets": unordered, "sum": 2.4, "count": 10}),
+            json!({"name": "latency", "buckets": not_cumulative, "sum": 2.4, "count": 10}),
+            json!({"name": "latency", "buckets": buckets, "sum": 2.4, "count": 5}),
+            json!({"name": "latency", "buckets": buckets, "count": 10}),
+        ];
+        for histogram in invalid.iter() {
+            assert!(parse(histogram.clone(), MessageType::Histogram).is_err(), "{}", histogram);
+        }
+    }
+}
@@ -1,25 +1,266 @@
+use std::sync::Arc;
+
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-#[derive(Debug, Deserialize, Serialize)]
+use crate::alerts::{ALERTS_CHANNEL, SILENCES_CHANNEL};
+use crate::clients::SESSION_CHANNEL;
+use crate::presence::PRESENCE_CHANNEL;
+use crate::rollups::QUERY_CHANNEL;
+use crate::router::REQUEST_CHANNEL;
+
+mod metadata;
+mod metric;
+mod payload;
+pub use metadata::{Metadata, Selector};
+pub use metric::Metric;
+pub use payload::Payload;
+
+pub const SYSTEM_CHANNEL: &str = "SYSTEM";
+
+// Channels only the server publishes on
+const RESERVED_CHANNELS: &[&str] = &[
+    SYSTEM_CHANNEL,
+    PRESENCE_CHANNEL,
+    ALERTS_CHANNEL,
+    SILENCES_CHANNEL,
+    REQUEST_CHANNEL,
+    QUERY_CHANNEL,
+    SESSION_CHANNEL,
+];
+
+pub fn is_reserved(channel: &str) -> bool {
+    RESERVED_CHANNELS.contains(&channel)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum MessageType {
     Error,
     Status,
     System,
+    Gauge,
+    Counter,
+    Histogram,
+    Ping,
+    Pong,
+    Request,
+    Reply,
+    // A monitor describing itself
+    Register,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Debug,
+    #[default]
+    Info,
+    Warning,
+    Error,
+    Critical,
+}
+
+impl MessageType {
+    pub fn is_metric(&self) -> bool {
+        matches!(self, MessageType::Gauge | MessageType::Counter | MessageType::Histogram)
+    }
+}
+
+// Who published a message: the authenticated identity and peer address of
+// the connection. Unix domain socket connections are not authenticated and
+// have neither.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Source {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identity: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer: Option<String>,
+}
+
+// Set on messages matching an acknowledgement
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Acknowledged {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub by: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub struct Message {
-    payload: Vec<u8>,
-    channel: Vec<u8>,
+    #[serde(flatten)]
+    payload: Payload,
+    #[serde(deserialize_with = "payload::string_or_bytes")]
+    channel: String,
     message_type: MessageType,
+    #[serde(default)]
+    severity: Severity,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sequence: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    received_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source: Option<Source>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    acknowledged: Option<Acknowledged>,
+    // Pairs a reply with its request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    correlation_id: Option<String>,
+    // What the monitor that published the message registered, for selectors
+    #[serde(skip)]
+    metadata: Option<Arc<Metadata>>,
 }
 
-pub fn status_msg(msg: &str) -> Message {
-    Message { 
-        payload: msg.into(),
-        channel: String::from("SYSTEM").into_bytes(),
-        message_type: MessageType::System,
+impl Message {
+    // A message published by the server itself
+    pub fn new(channel: &str, payload: Payload, message_type: MessageType, severity: Severity) -> Self {
+        Message {
+            payload,
+            channel: channel.to_owned(),
+            message_type,
+            severity,
+            id: None,
+            sequence: None,
+            received_at: None,
+            source: None,
+            acknowledged: None,
+            correlation_id: None,
+            metadata: None,
+        }
+    }
+
+    pub fn channel(&self) -> &str {
+        &self.channel
+    }
+
+    pub fn message_type(&self) -> MessageType {
+        self.message_type
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    pub fn source(&self) -> Option<&Source> {
+        self.source.as_ref()
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    pub fn sequence(&self) -> Option<u64> {
+        self.sequence
+    }
+
+    pub fn received_at(&self) -> Option<u64> {
+        self.received_at
+    }
+
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_deref()
+    }
+
+    pub fn correlation_id(&self) -> Option<&str> {
+        self.correlation_id.as_deref()
     }
+
+    // Resolve a path such as `payload.cpu` against the message.
+    // `payload` is the json payload (or the text of a utf8 payload) and `labels`
+    // the labels of a metric, any other field is looked up on the message itself.
+    pub fn lookup(&self, path: &[String]) -> Value {
+        let (root, rest) = match path.split_first() {
+            Some(split) => split,
+            None => return Value::Null,
+        };
+
+        let value = match (root.as_str(), &self.payload) {
+            ("payload", Payload::Json(value)) => return walk(value, rest),
+            ("labels", Payload::Json(value)) => return walk(&value["labels"], rest),
+            ("payload", Payload::Text(text)) => Value::String(text.clone()),
+            ("channel", _) => Value::String(self.channel.clone()),
+            ("severity", _) => serde_json::to_value(self.severity).unwrap_or_default(),
+            ("message_type", _) => serde_json::to_value(self.message_type).unwrap_or_default(),
+            ("id", _) => serde_json::to_value(&self.id).unwrap_or_default(),
+            ("sequence", _) => serde_json::to_value(self.sequence).unwrap_or_default(),
+            ("received_at", _) => serde_json::to_value(self.received_at).unwrap_or_default(),
+            ("source", _) => serde_json::to_value(&self.source).unwrap_or_default(),
+            ("acknowledged", _) => serde_json::to_value(&self.acknowledged).unwrap_or_default(),
+            _ => Value::Null,
+        };
+        walk(&value, rest)
+    }
+
+    // Metric messages must follow the metric schema, anything else is
+    // passed through as is.
+    pub fn validate(&self) -> Result<(), String> {
+        if is_reserved(&self.channel) {
+            return Err(format!("Channel \"{}\" is reserved for the server", self.channel));
+        }
+        if self.message_type.is_metric() {
+            Metric::parse(&self.payload, self.message_type)?;
+        }
+        Ok(())
+    }
+
+    // Set by the server, overwriting anything sent by the monitor
+    pub fn stamp(&mut self, id: String, sequence: u64, received_at: u64) {
+        self.id = Some(id);
+        self.sequence = Some(sequence);
+        self.received_at = Some(received_at);
+    }
+
+    // Set by the server, overwriting anything sent by the monitor
+    pub fn set_source(&mut self, source: Source) {
+        self.source = Some(source);
+    }
+
+    // Set by the server, overwriting anything sent by the monitor
+    pub fn set_metadata(&mut self, metadata: Option<Arc<Metadata>>) {
+        self.metadata = metadata;
+    }
+
+    pub fn set_correlation_id(&mut self, correlation_id: Option<String>) {
+        self.correlation_id = correlation_id;
+    }
+
+    // Set by the server, overwriting anything sent by the monitor
+    pub fn acknowledge(&mut self, acknowledged: Option<Acknowledged>) {
+        self.acknowledged = acknowledged;
+    }
+}
+
+fn walk(value: &Value, path: &[String]) -> Value {
+    path.iter()
+        .try_fold(value, |value, key| match value {
+            Value::Array(values) => key.parse::<usize>().ok().and_then(|i| values.get(i)),
+            value => value.get(key),
+        })
+        .cloned()
+        .unwrap_or(Value::Null)
+}
+
+pub fn status_msg(msg: &str) -> Message {
+    system_msg(msg, MessageType::System)
+}
+
+pub fn error_msg(msg: &str) -> Message {
+    system_msg(msg, MessageType::Error)
+}
+
+pub fn ping_msg() -> Message {
+    system_msg("ping", MessageType::Ping)
+}
+
+pub fn pong_msg() -> Message {
+    system_msg("pong", MessageType::Pong)
+}
+
+fn system_msg(msg: &str, message_type: MessageType) -> Message {
+    Message::new(SYSTEM_CHANNEL, msg.into(), message_type, Severity::default())
 }
@@ -0,0 +1,188 @@
+use std::fmt;
+
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::ser::{SerializeStruct, Serializer};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    Utf8,
+    Json,
+    Base64,
+}
+
+// Serialized as a `payload` field together with an explicit `encoding` field:
+//
+// {"payload": "OK", "encoding": "utf8"}
+// {"payload": {"cpu": 93}, "encoding": "json"}
+// {"payload": "AAEC", "encoding": "base64"}
+//
+// Without an `encoding` field strings are read as utf8, arrays of bytes
+// (the old form) as utf8 if valid or binary otherwise, and anything else as json.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Payload {
+    Text(String),
+    Json(Value),
+    Binary(Vec<u8>),
+}
+
+impl Payload {
+    pub fn encoding(&self) -> Encoding {
+        match self {
+            Payload::Text(_) => Encoding::Utf8,
+            Payload::Json(_) => Encoding::Json,
+            Payload::Binary(_) => Encoding::Base64,
+        }
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Payload {
+        match String::from_utf8(bytes) {
+            Ok(text) => Payload::Text(text),
+            Err(e) => Payload::Binary(e.into_bytes()),
+        }
+    }
+}
+
+impl From<&str> for Payload {
+    fn from(text: &str) -> Payload {
+        Payload::Text(text.to_owned())
+    }
+}
+
+impl From<Value> for Payload {
+    fn from(value: Value) -> Payload {
+        Payload::Json(value)
+    }
+}
+
+impl Serialize for Payload {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Payload", 2)?;
+        match self {
+            Payload::Text(text) => state.serialize_field("payload", text)?,
+            Payload::Json(value) => state.serialize_field("payload", value)?,
+            Payload::Binary(bytes) => state.serialize_field("payload", &base64::encode(bytes))?,
+        }
+        state.serialize_field("encoding", &self.encoding())?;
+        state.end()
+    }
+}
+
+#[derive(Deserialize)]
+struct RawPayload {
+    payload: Value,
+    #[serde(default)]
+    encoding: Option<Encoding>,
+}
+
+fn byte_array(value: &Value) -> Option<Vec<u8>> {
+    match value {
+        Value::Array(values) => values
+            .iter()
+            .map(|v| v.as_u64().filter(|&b| b <= 255).map(|b| b as u8))
+            .collect(),
+        _ => None,
+    }
+}
+
+impl<'de> Deserialize<'de> for Payload {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = RawPayload::deserialize(deserializer)?;
+
+        let payload = match (raw.encoding, raw.payload) {
+            (Some(Encoding::Json), value) => Payload::Json(value),
+            (Some(Encoding::Utf8), Value::String(text)) => Payload::Text(text),
+            (Some(Encoding::Base64), Value::String(text)) => {
+                Payload::Binary(base64::decode(&text).map_err(de::Error::custom)?)
+            }
+            (Some(encoding), value) => match byte_array(&value) {
+                Some(bytes) if encoding == Encoding::Base64 => Payload::Binary(bytes),
+                Some(bytes) => Payload::Text(String::from_utf8(bytes).map_err(de::Error::custom)?),
+                None => return Err(de::Error::custom("payload does not match its encoding")),
+            },
+            (None, Value::String(text)) => Payload::Text(text),
+            (None, value) => match byte_array(&value) {
+                Some(bytes) => Payload::from_bytes(bytes),
+                None => Payload::Json(value),
+            },
+        };
+
+        Ok(payload)
+    }
+}
+
+// Accepts a channel either as a string or, the old form, an array of bytes
+pub fn string_or_bytes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    struct StringOrBytes;
+
+    impl<'de> Visitor<'de> for StringOrBytes {
+        type Value = String;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a string or an array of utf8 bytes")
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<String, E> {
+            Ok(value.to_owned())
+        }
+
+        fn visit_string<E: de::Error>(self, value: String) -> Result<String, E> {
+            Ok(value)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<String, A::Error> {
+            let mut bytes = Vec::new();
+            while let Some(b) = seq.next_element::<u8>()? {
+                bytes.push(b);
+            }
+            String::from_utf8(bytes).map_err(de::Error::custom)
+        }
+    }
+
+    deserializer.deserialize_any(StringOrBytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse(value: Value) -> Result<Payload, serde_json::Error> {
+        serde_json::from_value(value)
+    }
+
+    #[test]
+    fn reads_explicit_encodings() {
+        let payload = parse(json!({ "payload": "OK", "encoding": "utf8" })).unwrap();
+        assert_eq!(payload, Payload::Text("OK".into()));
+        let payload = parse(json!({ "payload": { "cpu": 93 }, "encoding": "json" })).unwrap();
+        assert_eq!(payload, Payload::Json(json!({ "cpu": 93 })));
+        let payload = parse(json!({ "payload": "AAEC", "encoding": "base64" })).unwrap();
+        assert_eq!(payload, Payload::Binary(vec![0, 1, 2]));
+        let payload = parse(json!({ "payload": [0, 1, 255], "encoding": "base64" })).unwrap();
+        assert_eq!(payload, Payload::Binary(vec![0, 1, 255]));
+
+        assert!(parse(json!({ "payload": "not base64!", "encoding": "base64" })).is_err());
+        assert!(parse(json!({ "payload": { "cpu": 93 }, "encoding": "utf8" })).is_err());
+        assert!(parse(json!({ "payload": [255, 254], "encoding": "utf8" })).is_err());
+    }
+
+    #[test]
+    fn guesses_missing_encodings() {
+        assert_eq!(parse(json!({ "payload": "OK" })).unwrap(), Payload::Text("OK".into()));
+        assert_eq!(parse(json!({ "payload": [79, 75] })).unwrap(), Payload::Text("OK".into()));
+        assert_eq!(parse(json!({ "payload": [255, 0] })).unwrap(), Payload::Binary(vec![255, 0]));
+        assert_eq!(parse(json!({ "payload": 42 })).unwrap(), Payload::Json(json!(42)));
+        assert_eq!(parse(json!({ "payload": [1, 300] })).unwrap(), Payload::Json(json!([1, 300])));
+    }
+
+    #[test]
+    fn writes_the_encoding() {
+        let write = |payload: Payload| serde_json::to_value(payload).unwrap();
+        assert_eq!(write(Payload::Text("OK".into())), json!({ "payload": "OK", "encoding": "utf8" }));
+        assert_eq!(write(Payload::Json(json!({ "cpu": 93 }))), json!({ "payload": { "cpu": 93 }, "encoding": "json" }));
+        assert_eq!(write(Payload::Binary(vec![0, 1, 2])), json!({ "payload": "AAEC", "encoding": "base64" }));
+    }
+}
@@ -1,23 +1,67 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::{Read, Write};
+use std::sync::Arc;
+use std::time::Duration;
 
 use log::error;
-use bytes::Bytes;
+use sonr::errors::Result;
 use sonr::reactor::{Reaction, Reactor};
-use sonr::sync::broadcast::Broadcast;
+use sonr::sync::signal::ReactiveSignalReceiver;
+use sonr::sync::Capacity;
 use sonr::net::stream::StreamRef;
 use sonr::Token;
 use sonr_connection::{Codec, Connection};
 
-use crate::messages::{status_msg, Message};
+use crate::auth::Session;
+use crate::config::Config;
+use crate::messages::{error_msg, ping_msg, pong_msg, status_msg, Message, MessageType, Metadata, Severity, Source};
+use crate::presence::Presence;
+use crate::publisher::Publisher;
+use crate::ratelimit::{ExcessPolicy, RateLimiter};
+use crate::router::{Routed, Router, REQUEST_CHANNEL};
+use crate::timer::{ticker, Idle, IdleTimeout, Liveness};
+
+struct Monitor<T, C>
+where
+    T: StreamRef + Read + Write,
+    C: Codec,
+{
+    connection: Connection<T, C>,
+    source: Source,
+    liveness: Liveness,
+    // Id in the presence registry
+    presence_id: usize,
+    // Channels already reported to the presence registry
+    channels: HashSet<String>,
+    // Attached to every message published, for tag subscriptions
+    metadata: Option<Arc<Metadata>>,
+}
+
+impl<T, C> Monitor<T, C>
+where
+    T: StreamRef + Read + Write,
+    C: Codec,
+{
+    fn send(&mut self, message: Message) {
+        self.connection.add_write_buffer(C::encode(message));
+        self.connection.write_buffers();
+    }
+}
 
 pub struct Monitors<T, C>
 where
     T: StreamRef + Read + Write,
     C: Codec,
 {
-    connections: HashMap<Token, Connection<T, C>>,
-    broadcast: Broadcast<Bytes>,
+    connections: HashMap<Token, Monitor<T, C>>,
+    publisher: Publisher,
+    presence: Presence,
+    rate_limiter: RateLimiter,
+    router: Router,
+    // Requests from clients for the connections of this reactor
+    requests: ReactiveSignalReceiver<Routed>,
+    idle_timeout: IdleTimeout,
+    ticker: Option<ReactiveSignalReceiver<()>>,
 }
 
 impl<T, C> Monitors<T, C>
@@ -25,10 +69,71 @@ where
     T: StreamRef + Read + Write,
     C: Codec,
 {
-    pub fn new(broadcast: Broadcast<Bytes>) -> Self {
-        Self {
+    pub fn new(
+        publisher: Publisher,
+        presence: Presence,
+        rate_limiter: RateLimiter,
+        router: Router,
+        config: Arc<Config>,
+    ) -> Result<Self> {
+        let idle_timeout = IdleTimeout::new(config.timeouts.ping_interval, config.timeouts.monitor_idle);
+        let ticker = match idle_timeout.is_enabled() {
+            true => Some(ticker(Duration::from_secs(1))?),
+            false => None,
+        };
+
+        Ok(Self {
             connections: HashMap::new(),
-            broadcast,
+            publisher,
+            presence,
+            rate_limiter,
+            router,
+            requests: ReactiveSignalReceiver::new(Capacity::Unbounded.into())?,
+            idle_timeout,
+            ticker,
+        })
+    }
+
+    fn check_idle(&mut self) {
+        let idle_timeout = self.idle_timeout;
+        let mut expired = Vec::new();
+        for (token, monitor) in self.connections.iter_mut() {
+            match monitor.liveness.check(&idle_timeout) {
+                Idle::Active => {}
+                Idle::Ping => monitor.send(ping_msg()),
+                Idle::Expired => {
+                    monitor.send(error_msg("Idle timeout, disconnecting"));
+                    expired.push(*token);
+                }
+            }
+        }
+
+        for token in expired {
+            self.disconnect(token, "idle timeout");
+        }
+    }
+
+    fn disconnect(&mut self, token: Token, reason: &str) {
+        if let Some(monitor) = self.connections.remove(&token) {
+            if let Some(ref identity) = monitor.source.identity {
+                self.router.unregister(identity, monitor.presence_id);
+            }
+            self.presence.disconnected(monitor.presence_id, reason);
+            self.rate_limiter.disconnected(monitor.presence_id);
+        }
+    }
+
+    fn route(&mut self, routed: Routed) {
+        let monitor = self
+            .connections
+            .values_mut()
+            .find(|monitor| monitor.presence_id == routed.connection);
+        // Gone since, the client times out
+        if let Some(monitor) = monitor {
+            let mut request = Message::new(REQUEST_CHANNEL, routed.payload, MessageType::Request, Severity::Info);
+            request.set_correlation_id(Some(routed.correlation_id));
+            request.set_source(routed.source);
+            monitor.send(request);
         }
     }
 }
@@ -38,15 +143,34 @@ where
     T: StreamRef + Read + Write,
     C: Codec<Message=Message>,
 {
-    type Input = T;
+    type Input = Session<T>;
     type Output = ();
 
     fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
         match reaction {
             Reaction::Event(event) => {
-                let broadcast = &self.broadcast;
-                if let Some(con) = self.connections.get_mut(&event.token()) {
+                if let Some(ref ticker) = self.ticker {
+                    if event.token() == ticker.token() {
+                        while let Ok(()) = ticker.try_recv() {}
+                        self.check_idle();
+                        return Reaction::Continue;
+                    }
+                }
+
+                if event.token() == self.requests.token() {
+                    while let Ok(routed) = self.requests.try_recv() {
+                        self.route(routed);
+                    }
+                    return Reaction::Continue;
+                }
+
+                let publisher = &self.publisher;
+                let rate_limiter = &self.rate_limiter;
+                let router = &self.router;
+                let presence = &self.presence;
+                if let Some(monitor) = self.connections.get_mut(&event.token()) {
                     let mut messages = VecDeque::new();
+                    let con = &mut monitor.connection;
                     if let Reaction::Value(val) = con.react(event.into()) {
                         messages.push_back(val);
                         while let Reaction::Value(val) = con.react(Reaction::Continue) {
@@ -54,15 +178,81 @@ where
                         }
                     }
 
+                    if !messages.is_empty() {
+                        monitor.liveness.seen();
+                    }
+
                     for message in messages {
                         match message {
-                            Ok(msg) => {
-                                broadcast.publish(C::encode(msg));
+                            Ok(mut msg) => {
+                                match msg.message_type() {
+                                    MessageType::Ping => {
+                                        monitor.send(pong_msg());
+                                        continue;
+                                    }
+                                    MessageType::Pong => continue,
+                                    MessageType::Reply => {
+                                        match msg.correlation_id().map(String::from) {
+                                            Some(correlation_id) => {
+                                                msg.set_source(monitor.source.clone());
+                                                router.reply(&correlation_id, monitor.presence_id, msg);
+                                            }
+                                            None => monitor.send(error_msg("A reply needs a correlation_id")),
+                                        }
+                                        continue;
+                                    }
+                                    MessageType::Request => {
+                                        monitor.send(error_msg("Monitors can not send requests"));
+                                        continue;
+                                    }
+                                    _ => {}
+                                }
+                                if let Err(excess) = rate_limiter.check(&monitor.source, Some(monitor.presence_id), msg.channel()) {
+                                    if let Some(report) = excess.report_msg() {
+                                        publisher.publish(report);
+                                    }
+                                    match excess.policy {
+                                        ExcessPolicy::Drop => {}
+                                        ExcessPolicy::Error => {
+                                            if let Some(reason) = excess.reason() {
+                                                monitor.send(error_msg(&reason));
+                                            }
+                                        }
+                                        ExcessPolicy::Disconnect => {
+                                            monitor.send(error_msg("Rate limit exceeded, disconnecting"));
+                                            self.disconnect(event.token(), "rate limit exceeded");
+                                            return Reaction::Continue
+                                        }
+                                    }
+                                    continue;
+                                }
+                                if msg.message_type() == MessageType::Register {
+                                    match Metadata::parse(msg.lookup(&["payload".into()])) {
+                                        Ok(metadata) => {
+                                            monitor.metadata = Some(Arc::new(metadata.clone()));
+                                            presence.register(monitor.presence_id, metadata);
+                                            monitor.send(status_msg("OK"));
+                                        }
+                                        Err(reason) => monitor.send(error_msg(&reason)),
+                                    }
+                                    continue;
+                                }
+                                if let Err(reason) = msg.validate() {
+                                    monitor.send(error_msg(&reason));
+                                    continue;
+                                }
+                                if !monitor.channels.contains(msg.channel()) {
+                                    monitor.channels.insert(msg.channel().to_owned());
+                                    presence.published(monitor.presence_id, msg.channel());
+                                }
+                                msg.set_source(monitor.source.clone());
+                                msg.set_metadata(monitor.metadata.clone());
+                                publisher.publish(msg);
                             }
                             Err(e) => {
                                 error!("{:?}", e);
                                 // con.add_write_buffer(b"");
-                                self.connections.remove(&event.token());
+                                self.disconnect(event.token(), "connection closed");
                                 return Reaction::Continue
                             }
                         }
@@ -72,13 +262,28 @@ where
                     event.into()
                 }
             }
-            Reaction::Value(stream) => {
+            Reaction::Value(session) => {
                 let buf = status_msg("OK");
                 let bytes = C::encode(buf);
-                let mut connection = Connection::new(stream, C::default());
+                let mut connection = Connection::new(session.stream, C::default());
                 connection.add_write_buffer(bytes);
                 connection.write_buffers();
-                self.connections.insert(connection.token(), connection);
+                let presence_id = self.presence.connected(&session.source);
+                if let Some(ref metadata) = session.metadata {
+                    self.presence.register(presence_id, metadata.clone());
+                }
+                let monitor = Monitor {
+                    presence_id,
+                    connection,
+                    source: session.source,
+                    liveness: Liveness::new(),
+                    channels: HashSet::new(),
+                    metadata: session.metadata.map(Arc::new),
+                };
+                if let Some(ref identity) = monitor.source.identity {
+                    self.router.register(identity, monitor.presence_id, self.requests.sender());
+                }
+                self.connections.insert(monitor.connection.token(), monitor);
                 Reaction::Continue
             }
             Reaction::Continue => Reaction::Continue,
@@ -0,0 +1,209 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use serde_derive::Serialize;
+use serde_json::json;
+
+use crate::messages::{Message, MessageType, Metadata, Payload, Selector, Severity, Source};
+use crate::publisher::Publisher;
+use crate::timer::now_millis;
+
+pub const PRESENCE_CHANNEL: &str = "PRESENCE";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Online {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identity: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer: Option<String>,
+    // Milliseconds since the unix epoch
+    pub connected_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Metadata>,
+    // The channels published on since connecting
+    pub channels: BTreeSet<String>,
+}
+
+#[derive(Default)]
+struct Registry {
+    monitors: HashMap<usize, Online>,
+    next_id: usize,
+}
+
+// Keeps track of the monitors, returning the presence events to publish
+impl Registry {
+    fn connected(&mut self, source: &Source) -> (usize, Message) {
+        let id = self.next_id;
+        self.next_id += 1;
+        let online = Online {
+            identity: source.identity.clone(),
+            peer: source.peer.clone(),
+            connected_at: now_millis(),
+            metadata: None,
+            channels: BTreeSet::new(),
+        };
+        self.monitors.insert(id, online);
+
+        let payload = json!({
+            "status": "connected",
+            "identity": source.identity,
+            "peer": source.peer,
+        });
+        (id, presence_msg(payload.into(), Severity::Info))
+    }
+
+    fn disconnected(&mut self, id: usize, reason: &str) -> Option<Message> {
+        let online = self.monitors.remove(&id)?;
+        let payload = json!({
+            "status": "disconnected",
+            "identity": online.identity,
+            "peer": online.peer,
+            "reason": reason,
+        });
+        Some(presence_msg(payload.into(), Severity::Warning))
+    }
+
+    fn register(&mut self, id: usize, metadata: Metadata) -> Option<Message> {
+        let online = self.monitors.get_mut(&id)?;
+        let payload = json!({
+            "status": "registered",
+            "identity": online.identity,
+            "metadata": metadata,
+        });
+        online.metadata = Some(metadata);
+        Some(presence_msg(payload.into(), Severity::Info))
+    }
+
+    fn online(&self, selector: Option<&Selector>) -> Vec<Online> {
+        let mut online = self
+            .monitors
+            .values()
+            .filter(|o| selector.is_none_or(|s| s.matches(o.metadata.as_ref())))
+            .cloned()
+            .collect::<Vec<_>>();
+        online.sort_by_key(|o| o.connected_at);
+        online
+    }
+}
+
+// The monitors currently connected, on any thread. Monitors connecting and
+// disconnecting are published on the presence channel.
+#[derive(Clone)]
+pub struct Presence {
+    publisher: Publisher,
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl Presence {
+    pub fn new(publisher: Publisher) -> Self {
+        Self {
+            publisher,
+            registry: Arc::new(Mutex::new(Registry::default())),
+        }
+    }
+
+    fn registry(&self) -> MutexGuard<'_, Registry> {
+        match self.registry.lock() {
+            Ok(registry) => registry,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    // Returns the id to pass to `disconnected`
+    pub fn connected(&self, source: &Source) -> usize {
+        let (id, event) = self.registry().connected(source);
+        self.publisher.publish(event);
+        id
+    }
+
+    pub fn disconnected(&self, id: usize, reason: &str) {
+        let event = self.registry().disconnected(id, reason);
+        if let Some(event) = event {
+            self.publisher.publish(event);
+        }
+    }
+
+    // Replaces any metadata registered before on the connection
+    pub fn register(&self, id: usize, metadata: Metadata) {
+        let event = self.registry().register(id, metadata);
+        if let Some(event) = event {
+            self.publisher.publish(event);
+        }
+    }
+
+    pub fn published(&self, id: usize, channel: &str) {
+        if let Some(online) = self.registry().monitors.get_mut(&id) {
+            online.channels.insert(channel.to_owned());
+        }
+    }
+
+    pub fn online(&self, selector: Option<&Selector>) -> Vec<Online> {
+        self.registry().online(selector)
+    }
+
+    // Reply to a client asking which monitors are online
+    pub fn online_msg(&self, selector: Option<&Selector>) -> Message {
+        let payload = json!({ "monitors": self.online(selector) });
+        Message::new(PRESENCE_CHANNEL, payload.into(), MessageType::System, Severity::Info)
+    }
+}
+
+fn presence_msg(payload: Payload, severity: Severity) -> Message {
+    Message::new(PRESENCE_CHANNEL, payload, MessageType::System, severity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn field(event: &Message, name: &str) -> Value {
+        event.lookup(&["payload".into(), name.into()])
+    }
+
+    fn source(identity: &str) -> Source {
+        Source {
+            identity: Some(identity.into()),
+            peer: Some("10.0.0.1:4000".into()),
+        }
+    }
+
+    #[test]
+    fn connects_and_disconnects() {
+        let mut registry = Registry::default();
+        let (web, event) = registry.connected(&source("web"));
+        assert_eq!(event.channel(), PRESENCE_CHANNEL);
+        assert_eq!(field(&event, "status"), "connected");
+        assert_eq!(field(&event, "identity"), "web");
+        let (db, _) = registry.connected(&source("db"));
+        assert_ne!(web, db);
+        assert_eq!(registry.online(None).len(), 2);
+
+        let event = registry.disconnected(web, "idle").unwrap();
+        assert_eq!(event.severity(), Severity::Warning);
+        assert_eq!(field(&event, "status"), "disconnected");
+        assert_eq!(field(&event, "reason"), "idle");
+        assert!(registry.disconnected(web, "idle").is_none());
+
+        let online = registry.online(None);
+        assert_eq!(online.len(), 1);
+        assert_eq!(online[0].identity.as_deref(), Some("db"));
+    }
+
+    #[test]
+    fn registers_metadata() {
+        let mut registry = Registry::default();
+        let (id, _) = registry.connected(&source("web"));
+        let metadata = Metadata::parse(json!({ "service": "api" })).unwrap();
+
+        let event = registry.register(id, metadata.clone()).unwrap();
+        assert_eq!(field(&event, "status"), "registered");
+        assert_eq!(field(&event, "metadata"), json!({ "service": "api" }));
+        assert!(registry.register(id + 1, metadata).is_none());
+
+        let api = Selector::parse("service=api").unwrap();
+        let db = Selector::parse("service=db").unwrap();
+        assert_eq!(registry.online(Some(&api)).len(), 1);
+        assert!(registry.online(Some(&db)).is_empty());
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use sonr::sync::broadcast::Broadcast;
+
+use crate::alerts::Silences;
+use crate::history::History;
+use crate::messages::Message;
+use crate::timer::now_millis;
+
+struct Sequences {
+    channels: HashMap<String, u64>,
+    next_id: u64,
+}
+
+// Stamps every message with an id, a per channel sequence number and the
+// time it was received before publishing it. Acknowledged messages are
+// annotated, silences apply when messages are delivered.
+//
+// Shared between all threads. Stamping and publishing happens under the same
+// lock so subscribers always see sequence numbers in order.
+#[derive(Clone)]
+pub struct Publisher {
+    broadcast: Broadcast<Arc<Message>>,
+    sequences: Arc<Mutex<Sequences>>,
+    silences: Silences,
+    history: History,
+    epoch: u64,
+}
+
+impl Publisher {
+    pub fn new(broadcast: Broadcast<Arc<Message>>, silences: Silences, history: History) -> Self {
+        let sequences = Sequences {
+            channels: HashMap::new(),
+            next_id: 0,
+        };
+
+        Self {
+            broadcast,
+            sequences: Arc::new(Mutex::new(sequences)),
+            silences,
+            history,
+            epoch: now_millis(),
+        }
+    }
+
+    pub fn publish(&self, mut message: Message) {
+        self.silences.annotate(&mut message);
+
+        let received_at = now_millis();
+        let mut sequences = match self.sequences.lock() {
+            Ok(sequences) => sequences,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        sequences.next_id += 1;
+        let id = format!("{:x}-{:x}", self.epoch, sequences.next_id);
+        let sequence = {
+            let sequence = sequences
+                .channels
+                .entry(message.channel().to_owned())
+                .or_insert(0);
+            *sequence += 1;
+            *sequence
+        };
+
+        message.stamp(id, sequence, received_at);
+        let message = Arc::new(message);
+        self.history.push(message.clone());
+        self.broadcast.publish(message);
+    }
+}
@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use log::info;
+use serde_derive::Deserialize;
+use serde_json::json;
+
+use crate::messages::{Message, MessageType, Severity, Source, SYSTEM_CHANNEL};
+
+// How often a monitor over its limit is told how many messages were dropped
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct RateLimit {
+    // Messages per second
+    pub rate: f64,
+    // Messages that can be sent at once after being idle
+    pub burst: f64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExcessPolicy {
+    Drop,
+    #[default]
+    Error,
+    Disconnect,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PublishLimits {
+    // Applies to each monitor identity, unless overridden in `monitors`
+    pub monitor: Option<RateLimit>,
+    pub monitors: HashMap<String, RateLimit>,
+    // Applies to each channel
+    pub channel: Option<RateLimit>,
+    pub on_excess: ExcessPolicy,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(limit: &RateLimit) -> Self {
+        Self {
+            tokens: limit.burst,
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, limit: &RateLimit) {
+        let elapsed = self.updated.elapsed();
+        let elapsed = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+        self.tokens = (self.tokens + elapsed * limit.rate).min(limit.burst);
+        self.updated = Instant::now();
+    }
+}
+
+// Monitors without an identity are limited per connection
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Identity(String),
+    Connection(usize),
+}
+
+#[derive(Default)]
+struct Buckets {
+    monitors: HashMap<Key, Bucket>,
+    channels: HashMap<String, Bucket>,
+    dropped: HashMap<Key, Dropped>,
+    totals: Totals,
+}
+
+// Messages dropped per monitor since they were last reported
+struct Dropped {
+    count: u64,
+    reported_at: Option<Instant>,
+}
+
+// Messages dropped since the server started, per monitor identity (anonymous
+// monitors together) and per channel
+#[derive(Default)]
+struct Totals {
+    monitors: HashMap<Option<String>, u64>,
+    channels: HashMap<String, u64>,
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub identity: Option<String>,
+    pub channel: String,
+    // Since the last report
+    pub dropped: u64,
+    pub monitor_total: u64,
+    pub channel_total: u64,
+}
+
+pub struct Excess {
+    pub policy: ExcessPolicy,
+    // At most once per `REPORT_INTERVAL` and monitor
+    pub report: Option<Report>,
+}
+
+impl Excess {
+    // The error to reply with, if it's time to report
+    pub fn reason(&self) -> Option<String> {
+        self.report
+            .as_ref()
+            .map(|report| format!("Rate limit exceeded, {} messages dropped", report.dropped))
+    }
+
+    // Published on the system channel, so the totals can be followed
+    pub fn report_msg(&self) -> Option<Message> {
+        let report = self.report.as_ref()?;
+        let payload = json!({
+            "status": "rate_limited",
+            "identity": report.identity,
+            "channel": report.channel,
+            "dropped": report.dropped,
+            "monitor_total": report.monitor_total,
+            "channel_total": report.channel_total,
+        });
+        Some(Message::new(SYSTEM_CHANNEL, payload.into(), MessageType::System, Severity::Warning))
+    }
+}
+
+// Token buckets per monitor identity and per channel, shared between
+// all threads as a monitor can have connections on more than one.
+#[derive(Clone)]
+pub struct RateLimiter {
+    limits: Arc<PublishLimits>,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: PublishLimits) -> Self {
+        Self {
+            limits: Arc::new(limits),
+            buckets: Arc::new(Mutex::new(Buckets::default())),
+        }
+    }
+
+    fn buckets(&self) -> MutexGuard<'_, Buckets> {
+        match self.buckets.lock() {
+            Ok(buckets) => buckets,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    // Take a token from both the monitor's and the channel's bucket,
+    // or neither if either of them is empty. `connection` tells monitors
+    // without an identity apart, clients need one to publish.
+    pub fn check(&self, source: &Source, connection: Option<usize>, channel: &str) -> Result<(), Excess> {
+        let key = match (&source.identity, connection) {
+            (Some(identity), _) => Key::Identity(identity.clone()),
+            (None, Some(connection)) => Key::Connection(connection),
+            (None, None) => Key::Identity(String::new()),
+        };
+        let monitor_limit = match key {
+            Key::Identity(ref identity) => self.limits.monitors.get(identity),
+            Key::Connection(_) => None,
+        };
+        let monitor_limit = monitor_limit.or(self.limits.monitor.as_ref());
+        let channel_limit = self.limits.channel.as_ref();
+        if monitor_limit.is_none() && channel_limit.is_none() {
+            return Ok(());
+        }
+
+        let mut buckets = self.buckets();
+        let Buckets { monitors, channels, dropped, totals } = &mut *buckets;
+
+        let mut taken = Vec::new();
+        if let Some(limit) = monitor_limit {
+            let bucket = monitors.entry(key.clone()).or_insert_with(|| Bucket::new(limit));
+            bucket.refill(limit);
+            taken.push(bucket);
+        }
+        if let Some(limit) = channel_limit {
+            let bucket = channels.entry(channel.to_owned()).or_insert_with(|| Bucket::new(limit));
+            bucket.refill(limit);
+            taken.push(bucket);
+        }
+
+        if taken.iter().all(|bucket| bucket.tokens >= 1.0) {
+            taken.into_iter().for_each(|bucket| bucket.tokens -= 1.0);
+            return Ok(());
+        }
+
+        let monitor_total = totals.monitors.entry(source.identity.clone()).or_insert(0);
+        *monitor_total += 1;
+        let channel_total = totals.channels.entry(channel.to_owned()).or_insert(0);
+        *channel_total += 1;
+
+        let dropped = dropped.entry(key).or_insert(Dropped {
+            count: 0,
+            reported_at: None,
+        });
+        dropped.count += 1;
+        let report = match dropped.reported_at {
+            Some(at) if at.elapsed() < REPORT_INTERVAL => None,
+            _ => {
+                let identity = source.identity.as_deref().unwrap_or("");
+                info!("Rate limiting monitor \"{}\" on channel \"{}\", {} messages dropped", identity, channel, dropped.count);
+                dropped.reported_at = Some(Instant::now());
+                Some(Report {
+                    identity: source.identity.clone(),
+                    channel: channel.to_owned(),
+                    dropped: mem::replace(&mut dropped.count, 0),
+                    monitor_total: *monitor_total,
+                    channel_total: *channel_total,
+                })
+            }
+        };
+
+        Err(Excess {
+            policy: self.limits.on_excess,
+            report,
+        })
+    }
+
+    // Forget the bucket of a monitor without an identity
+    pub fn disconnected(&self, connection: usize) {
+        let mut buckets = self.buckets();
+        buckets.monitors.remove(&Key::Connection(connection));
+        buckets.dropped.remove(&Key::Connection(connection));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_dropped_messages_once_per_interval() {
+        let limits = PublishLimits {
+            monitor: Some(RateLimit { rate: 0.0, burst: 1.0 }),
+            ..PublishLimits::default()
+        };
+        let limiter = RateLimiter::new(limits);
+        let source = Source {
+            identity: Some("web1".into()),
+            peer: None,
+        };
+
+        assert!(limiter.check(&source, None, "web1").is_ok());
+        let reports = (0..3)
+            .map(|_| limiter.check(&source, None, "web1").err().map(|excess| excess.report.map(|r| r.dropped)))
+            .collect::<Vec<_>>();
+        assert_eq!(reports, vec![Some(Some(1)), Some(None), Some(None)]);
+    }
+
+    #[test]
+    fn keeps_totals() {
+        let limits = PublishLimits {
+            monitor: Some(RateLimit { rate: 0.0, burst: 0.0 }),
+            ..PublishLimits::default()
+        };
+        let limiter = RateLimiter::new(limits);
+        let web1 = Source {
+            identity: Some("web1".into()),
+            peer: None,
+        };
+        let web2 = Source {
+            identity: Some("web2".into()),
+            peer: None,
+        };
+
+        let report = limiter.check(&web1, None, "cpu").unwrap_err().report.unwrap();
+        assert_eq!((report.dropped, report.monitor_total, report.channel_total), (1, 1, 1));
+        assert!(limiter.check(&web1, None, "cpu").unwrap_err().report.is_none());
+        let excess = limiter.check(&web2, None, "cpu").unwrap_err();
+        let report = excess.report.as_ref().unwrap();
+        assert_eq!((report.dropped, report.monitor_total, report.channel_total), (1, 1, 3));
+
+        let message = excess.report_msg().unwrap();
+        assert_eq!(message.channel(), SYSTEM_CHANNEL);
+        assert_eq!(message.lookup(&["payload".into(), "identity".into()]), "web2");
+        assert_eq!(message.lookup(&["payload".into(), "channel_total".into()]), 3);
+    }
+
+    #[test]
+    fn limits_anonymous_monitors_per_connection() {
+        let limits = PublishLimits {
+            monitor: Some(RateLimit { rate: 0.0, burst: 1.0 }),
+            ..PublishLimits::default()
+        };
+        let limiter = RateLimiter::new(limits);
+        let anonymous = Source::default();
+
+        assert!(limiter.check(&anonymous, Some(1), "cpu").is_ok());
+        assert!(limiter.check(&anonymous, Some(1), "cpu").is_err());
+        // Another local monitor has a bucket of its own
+        assert!(limiter.check(&anonymous, Some(2), "cpu").is_ok());
+
+        // A new connection reusing the id starts with a full bucket
+        limiter.disconnected(1);
+        assert!(limiter.check(&anonymous, Some(1), "cpu").is_ok());
+    }
+}
@@ -0,0 +1,333 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::error;
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+use sonr::errors::Result;
+use sonr::reactor::{Reaction, Reactor};
+use sonr::sync::signal::{ReactiveSignalReceiver, SignalReceiver, SignalSender};
+use sonr::Token;
+
+use crate::filter::glob;
+use crate::messages::{error_msg, Message, MessageType, Severity};
+use crate::timer::{now_millis, ticker};
+
+mod store;
+pub use store::Store;
+use store::{Point, Writer};
+
+pub const QUERY_CHANNEL: &str = "QUERY";
+
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(60);
+// Queries waiting to run, anything more is refused
+const MAX_QUEUED_QUERIES: usize = 100;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Tier {
+    // Seconds per point, 0 keeps every value
+    pub resolution: u64,
+    // Seconds points are kept for
+    pub retention: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RollupConfig {
+    // Channel patterns, `*` matches any number of characters.
+    // No channels means no rollups.
+    pub channels: Vec<String>,
+    // Path to the numeric value, as in filters
+    pub field: String,
+    // From the finest resolution to the coarsest
+    pub tiers: Vec<Tier>,
+}
+
+impl Default for RollupConfig {
+    fn default() -> Self {
+        let day = 24 * 60 * 60;
+        Self {
+            channels: Vec::new(),
+            field: "payload.value".into(),
+            tiers: vec![
+                Tier { resolution: 0, retention: day },
+                Tier { resolution: 60, retention: 30 * day },
+                Tier { resolution: 3600, retention: 365 * day },
+            ],
+        }
+    }
+}
+
+impl RollupConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.channels.is_empty() && !self.tiers.is_empty()
+    }
+}
+
+// A client asking for the points of a channel between two times
+#[derive(Debug, Deserialize)]
+pub struct Query {
+    channel: String,
+    // The metric name, for channels with more than one metric
+    #[serde(default)]
+    name: Option<String>,
+    // Milliseconds since the unix epoch
+    from: u64,
+    #[serde(default)]
+    to: Option<u64>,
+    // Seconds, picked from how far back `from` is if left out
+    #[serde(default)]
+    resolution: Option<u64>,
+}
+
+impl Query {
+    pub fn run(self, store: &Store) -> std::result::Result<Message, String> {
+        let to = self.to.unwrap_or_else(now_millis);
+        if to < self.from {
+            return Err("to must not be before from".into());
+        }
+
+        let tier = store.tier(self.from, self.resolution)?;
+        let name = self.name.as_deref();
+        let points = store
+            .query(&tier, &self.channel, name, self.from, to)
+            .map_err(|e| format!("Query failed: {}", e))?;
+
+        let points = points
+            .iter()
+            .map(|p| json!({
+                "t": p.t,
+                "min": p.min,
+                "max": p.max,
+                "avg": p.sum / p.count as f64,
+                "count": p.count,
+            }))
+            .collect::<Vec<_>>();
+        let payload = json!({
+            "channel": self.channel,
+            "name": self.name,
+            "resolution": tier.resolution,
+            "points": points,
+        });
+        Ok(Message::new(QUERY_CHANNEL, payload.into(), MessageType::System, Severity::Info))
+    }
+}
+
+// The answer to a query, for the client of `token`
+pub struct QueryReply {
+    pub token: Token,
+    pub message: Message,
+}
+
+enum Job {
+    Query {
+        query: Query,
+        token: Token,
+        reply_to: SignalSender<QueryReply>,
+    },
+    Write(Vec<(Tier, Point)>),
+    // Write out buffered points, and remove expired segments if `expire` is set
+    Flush {
+        expire: bool,
+    },
+}
+
+// Reads and writes segment files, one job at a time on a thread of its own,
+// so disk I/O never holds up the reactors.
+fn spawn(store: Store, queued: Arc<AtomicUsize>) -> Sender<Job> {
+    let (jobs, queue) = channel::<Job>();
+    thread::spawn(move || {
+        let mut writer = Writer::new(store);
+        for job in queue {
+            match job {
+                Job::Query { query, token, reply_to } => {
+                    let message = query.run(writer.store()).unwrap_or_else(|reason| error_msg(&reason));
+                    let _ = reply_to.send(QueryReply { token, message });
+                    queued.fetch_sub(1, Ordering::SeqCst);
+                }
+                Job::Write(points) => {
+                    let written = points.iter().try_for_each(|(tier, point)| writer.append(tier, point));
+                    if let Err(e) = written {
+                        error!("Failed to write rollups: {:?}", e);
+                    }
+                }
+                Job::Flush { expire } => {
+                    let mut flushed = writer.flush();
+                    if expire {
+                        flushed = flushed.and_then(|()| writer.store().expire());
+                    }
+                    if let Err(e) = flushed {
+                        error!("Failed to write rollups: {:?}", e);
+                    }
+                }
+            }
+        }
+    });
+    jobs
+}
+
+// Hands queries to the rollups' thread, for the clients on every thread
+#[derive(Clone)]
+pub struct Queries {
+    jobs: Sender<Job>,
+    // Queries sent but not yet run
+    queued: Arc<AtomicUsize>,
+}
+
+impl Queries {
+    pub fn run(&self, query: Query, token: Token, reply_to: SignalSender<QueryReply>) -> std::result::Result<(), String> {
+        if self.queued.fetch_add(1, Ordering::SeqCst) >= MAX_QUEUED_QUERIES {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            return Err("Too many queries waiting, try again later".into());
+        }
+        self.jobs.send(Job::Query { query, token, reply_to }).map_err(|_| {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            "Rollups are not available".to_string()
+        })
+    }
+}
+
+// Writes the numeric values of the configured channels to disk, every value
+// and aggregated per tier resolution.
+pub struct Rollups {
+    receiver: ReactiveSignalReceiver<Arc<Message>>,
+    ticker: ReactiveSignalReceiver<()>,
+    channels: Vec<String>,
+    field: Vec<String>,
+    tiers: Vec<Tier>,
+    jobs: Sender<Job>,
+    queued: Arc<AtomicUsize>,
+    // The point being aggregated per tier resolution, channel and metric name
+    buckets: HashMap<(u64, String, Option<String>), Point>,
+    // Points to hand to the store thread with the next write
+    points: Vec<(Tier, Point)>,
+    last_expired: Instant,
+}
+
+impl Rollups {
+    pub fn new(receiver: SignalReceiver<Arc<Message>>, store: Store, config: &RollupConfig) -> Result<Self> {
+        let tiers = store.tiers().to_vec();
+        let queued = Arc::new(AtomicUsize::new(0));
+        Ok(Self {
+            receiver: ReactiveSignalReceiver::new(receiver)?,
+            ticker: ticker(Duration::from_secs(1))?,
+            channels: config.channels.clone(),
+            field: config.field.split('.').map(String::from).collect(),
+            tiers,
+            jobs: spawn(store, queued.clone()),
+            queued,
+            buckets: HashMap::new(),
+            points: Vec::new(),
+            last_expired: Instant::now(),
+        })
+    }
+
+    pub fn queries(&self) -> Queries {
+        Queries { jobs: self.jobs.clone(), queued: self.queued.clone() }
+    }
+
+    fn add(&mut self, message: &Message) {
+        if !self.channels.iter().any(|c| glob(c, message.channel())) {
+            return;
+        }
+        let value = match message.lookup(&self.field).as_f64() {
+            Some(value) if value.is_finite() => value,
+            _ => return,
+        };
+        let name = match message.lookup(&["payload".into(), "name".into()]) {
+            Value::String(name) => Some(name),
+            _ => None,
+        };
+        let t = message.received_at().unwrap_or_else(now_millis);
+        let channel = message.channel().to_owned();
+
+        for tier in &self.tiers {
+            if tier.resolution == 0 {
+                self.points.push((*tier, Point::new(t, channel.clone(), name.clone(), value)));
+                continue;
+            }
+
+            let start = t - t % (tier.resolution * 1000);
+            let key = (tier.resolution, channel.clone(), name.clone());
+            match self.buckets.get_mut(&key) {
+                Some(point) if point.t == start => point.add(value),
+                _ => {
+                    let point = Point::new(start, channel.clone(), name.clone(), value);
+                    if let Some(done) = self.buckets.insert(key, point) {
+                        self.points.push((*tier, done));
+                    }
+                }
+            }
+        }
+    }
+
+    // Hand the buffered points to the store thread
+    fn write(&mut self) {
+        if self.points.is_empty() {
+            return;
+        }
+        let points = std::mem::take(&mut self.points);
+        if self.jobs.send(Job::Write(points)).is_err() {
+            error!("Failed to write rollups: the store thread has stopped");
+        }
+    }
+
+    // Write the points of buckets that have ended, even without newer values
+    fn tick(&mut self) {
+        let now = now_millis();
+        let done = self
+            .buckets
+            .iter()
+            .filter(|((resolution, _, _), point)| point.t + resolution * 1000 <= now)
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+
+        for key in done {
+            let tier = self.tiers.iter().find(|t| t.resolution == key.0).cloned();
+            if let (Some(tier), Some(point)) = (tier, self.buckets.remove(&key)) {
+                self.points.push((tier, point));
+            }
+        }
+        self.write();
+
+        let expire = self.last_expired.elapsed() > EXPIRE_INTERVAL;
+        if expire {
+            self.last_expired = Instant::now();
+        }
+        if self.jobs.send(Job::Flush { expire }).is_err() {
+            error!("Failed to write rollups: the store thread has stopped");
+        }
+    }
+}
+
+impl Reactor for Rollups {
+    type Input = ();
+    type Output = ();
+
+    fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
+        match reaction {
+            Reaction::Event(event) => {
+                if event.token() == self.ticker.token() {
+                    while let Ok(()) = self.ticker.try_recv() {}
+                    self.tick();
+                    return Reaction::Continue;
+                }
+
+                if event.token() == self.receiver.token() {
+                    while let Ok(message) = self.receiver.try_recv() {
+                        self.add(&message);
+                    }
+                    self.write();
+                    return Reaction::Continue;
+                }
+
+                event.into()
+            }
+            Reaction::Value(()) | Reaction::Continue => Reaction::Continue,
+        }
+    }
+}
@@ -0,0 +1,317 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Write};
+use std::path::PathBuf;
+
+use serde_derive::{Deserialize, Serialize};
+
+use super::Tier;
+use crate::timer::now_millis;
+
+// Most points returned by a single query
+const MAX_POINTS: usize = 10_000;
+
+// A value, or the values within `resolution` seconds of `t`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Point {
+    // Milliseconds since the unix epoch
+    pub t: u64,
+    pub channel: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub min: f64,
+    pub max: f64,
+    pub sum: f64,
+    pub count: u64,
+}
+
+impl Point {
+    pub fn new(t: u64, channel: String, name: Option<String>, value: f64) -> Self {
+        Self {
+            t,
+            channel,
+            name,
+            min: value,
+            max: value,
+            sum: value,
+            count: 1,
+        }
+    }
+
+    pub fn add(&mut self, value: f64) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+fn segment_length(tier: &Tier) -> u64 {
+    (tier.resolution * 1000).max(3600) * 1000
+}
+
+// Points are kept in a directory per tier, in files of newline separated
+// json covering a fixed length of time each, so expired points can be
+// removed a file at a time.
+#[derive(Debug, Clone)]
+pub struct Store {
+    dir: PathBuf,
+    tiers: Vec<Tier>,
+}
+
+impl Store {
+    pub fn new(dir: PathBuf, tiers: Vec<Tier>) -> io::Result<Self> {
+        for tier in &tiers {
+            fs::create_dir_all(dir.join(tier.resolution.to_string()))?;
+        }
+        Ok(Self { dir, tiers })
+    }
+
+    pub fn tiers(&self) -> &[Tier] {
+        &self.tiers
+    }
+
+    fn segment_path(&self, tier: &Tier, start: u64) -> PathBuf {
+        self.dir
+            .join(tier.resolution.to_string())
+            .join(format!("{}.jsonl", start))
+    }
+
+    fn segments(&self, tier: &Tier) -> io::Result<Vec<u64>> {
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(self.dir.join(tier.resolution.to_string()))? {
+            let name = entry?.file_name();
+            let start = name
+                .to_str()
+                .and_then(|name| name.trim_end_matches(".jsonl").parse().ok());
+            if let Some(start) = start {
+                segments.push(start);
+            }
+        }
+        segments.sort();
+        Ok(segments)
+    }
+
+    // Remove segments entirely older than the retention of their tier
+    pub fn expire(&self) -> io::Result<()> {
+        let now = now_millis();
+        for tier in &self.tiers {
+            let length = segment_length(tier);
+            for start in self.segments(tier)? {
+                if start + length + tier.retention * 1000 < now {
+                    fs::remove_file(self.segment_path(tier, start))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // The finest tier still holding points from `from`, unless a resolution is asked for
+    pub fn tier(&self, from: u64, resolution: Option<u64>) -> Result<Tier, String> {
+        if let Some(resolution) = resolution {
+            return self
+                .tiers
+                .iter()
+                .find(|t| t.resolution == resolution)
+                .cloned()
+                .ok_or_else(|| format!("No rollups with a resolution of {} seconds", resolution));
+        }
+
+        let age = now_millis().saturating_sub(from);
+        self.tiers
+            .iter()
+            .find(|t| t.retention * 1000 >= age)
+            .or_else(|| self.tiers.last())
+            .cloned()
+            .ok_or_else(|| "Rollups are not enabled".into())
+    }
+
+    pub fn query(&self, tier: &Tier, channel: &str, name: Option<&str>, from: u64, to: u64) -> io::Result<Vec<Point>> {
+        let length = segment_length(tier);
+        let mut points = Vec::new();
+        for start in self.segments(tier)? {
+            if start + length < from || start > to {
+                continue;
+            }
+
+            let file = match File::open(self.segment_path(tier, start)) {
+                Ok(file) => file,
+                Err(ref e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            for line in BufReader::new(file).lines() {
+                // A partly written last line is skipped
+                let point = match serde_json::from_str::<Point>(&line?) {
+                    Ok(point) => point,
+                    Err(_) => continue,
+                };
+                if point.t >= from && point.t <= to && point.channel == channel && point.name.as_deref() == name {
+                    points.push(point);
+                    if points.len() == MAX_POINTS {
+                        return Ok(points);
+                    }
+                }
+            }
+        }
+        Ok(points)
+    }
+}
+
+// Appends points to the current segment of each tier
+pub struct Writer {
+    store: Store,
+    open: HashMap<u64, (u64, BufWriter<File>)>,
+}
+
+impl Writer {
+    pub fn new(store: Store) -> Self {
+        Self {
+            store,
+            open: HashMap::new(),
+        }
+    }
+
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
+
+    pub fn append(&mut self, tier: &Tier, point: &Point) -> io::Result<()> {
+        let start = point.t - point.t % segment_length(tier);
+        let current = self.open.get(&tier.resolution).map(|(start, _)| *start);
+        if current != Some(start) {
+            if let Some((_, mut writer)) = self.open.remove(&tier.resolution) {
+                writer.flush()?;
+            }
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.store.segment_path(tier, start))?;
+            self.open.insert(tier.resolution, (start, BufWriter::new(file)));
+        }
+
+        if let Some((_, writer)) = self.open.get_mut(&tier.resolution) {
+            serde_json::to_writer(&mut *writer, point)?;
+            writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        for (_, writer) in self.open.values_mut() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    const HOUR: u64 = 3600 * 1000;
+
+    fn store(name: &str) -> Store {
+        let dir = env::temp_dir().join(format!("remonitor-rollups-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let tiers = vec![
+            Tier { resolution: 0, retention: 3600 },
+            Tier { resolution: 60, retention: 24 * 3600 },
+        ];
+        Store::new(dir, tiers).unwrap()
+    }
+
+    fn times(points: &[Point]) -> Vec<u64> {
+        points.iter().map(|p| p.t).collect()
+    }
+
+    #[test]
+    fn writes_points_to_segments() {
+        let store = store("segments");
+        let tier = store.tiers()[0];
+        let now = now_millis();
+        let start = now - now % HOUR;
+
+        let mut writer = Writer::new(store.clone());
+        for t in [start - HOUR + 5, start - 1, start, start + 5] {
+            writer.append(&tier, &Point::new(t, "cpu".into(), None, 1.0)).unwrap();
+        }
+        writer.flush().unwrap();
+
+        assert_eq!(store.segments(&tier).unwrap(), vec![start - HOUR, start]);
+        let lines = fs::read_to_string(store.segment_path(&tier, start - HOUR)).unwrap();
+        assert_eq!(lines.lines().count(), 2);
+        fs::remove_dir_all(&store.dir).unwrap();
+    }
+
+    #[test]
+    fn queries_ranges() {
+        let store = store("query");
+        let tier = store.tiers()[0];
+        let now = now_millis();
+        let start = now - now % HOUR - 2 * HOUR;
+
+        let mut writer = Writer::new(store.clone());
+        for (t, channel, name) in [
+            (start, "cpu", None),
+            (start + HOUR, "cpu", None),
+            (start + HOUR + 1, "cpu", Some("user")),
+            (start + HOUR + 2, "mem", None),
+            (start + 2 * HOUR, "cpu", None),
+        ] {
+            let point = Point::new(t, channel.into(), name.map(String::from), 1.0);
+            writer.append(&tier, &point).unwrap();
+        }
+        writer.flush().unwrap();
+
+        let points = store.query(&tier, "cpu", None, start, now).unwrap();
+        assert_eq!(times(&points), vec![start, start + HOUR, start + 2 * HOUR]);
+        let points = store.query(&tier, "cpu", None, start + 1, start + HOUR).unwrap();
+        assert_eq!(times(&points), vec![start + HOUR]);
+        let points = store.query(&tier, "cpu", Some("user"), start, now).unwrap();
+        assert_eq!(times(&points), vec![start + HOUR + 1]);
+        assert!(store.query(&tier, "disk", None, start, now).unwrap().is_empty());
+
+        // A partly written last line is skipped
+        let mut file = OpenOptions::new().append(true).open(store.segment_path(&tier, start)).unwrap();
+        file.write_all(b"{\"t\": 1").unwrap();
+        assert_eq!(store.query(&tier, "cpu", None, start, start).unwrap().len(), 1);
+        fs::remove_dir_all(&store.dir).unwrap();
+    }
+
+    #[test]
+    fn expires_old_segments() {
+        let store = store("expire");
+        let now = now_millis();
+        let old = now - 72 * HOUR;
+
+        let mut writer = Writer::new(store.clone());
+        for tier in store.tiers().to_vec() {
+            writer.append(&tier, &Point::new(old, "cpu".into(), None, 1.0)).unwrap();
+            writer.append(&tier, &Point::new(now, "cpu".into(), None, 1.0)).unwrap();
+        }
+        writer.flush().unwrap();
+
+        store.expire().unwrap();
+        for tier in store.tiers() {
+            let segments = store.segments(tier).unwrap();
+            assert_eq!(segments, vec![now - now % segment_length(tier)]);
+        }
+        fs::remove_dir_all(&store.dir).unwrap();
+    }
+
+    #[test]
+    fn picks_tiers() {
+        let store = store("tiers");
+        let now = now_millis();
+
+        assert_eq!(store.tier(now - 600 * 1000, None).unwrap().resolution, 0);
+        assert_eq!(store.tier(now - 2 * HOUR, None).unwrap().resolution, 60);
+        // Older than every tier keeps, the coarsest is the closest
+        assert_eq!(store.tier(now - 240 * HOUR, None).unwrap().resolution, 60);
+        assert_eq!(store.tier(now, Some(60)).unwrap().resolution, 60);
+        assert!(store.tier(now, Some(5)).is_err());
+        fs::remove_dir_all(&store.dir).unwrap();
+    }
+}
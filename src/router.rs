@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use sonr::sync::signal::SignalSender;
+
+use crate::messages::{Message, Payload, Source};
+use crate::timer::now_millis;
+
+// The channel requests are sent to monitors on
+pub const REQUEST_CHANNEL: &str = "REQUEST";
+
+// A request on its way to a monitor connection
+pub struct Routed {
+    pub correlation_id: String,
+    // The presence id of the connection
+    pub connection: usize,
+    pub payload: Payload,
+    // Who is asking
+    pub source: Source,
+}
+
+// A reply on its way back to a client
+pub struct Reply {
+    pub correlation_id: String,
+    pub message: Message,
+}
+
+struct Pending<R> {
+    // The presence id of the connection the request went to
+    connection: usize,
+    reply_to: R,
+}
+
+// Monitor connections and the requests waiting on them, with the senders to
+// monitors as `M` and to clients as `R`
+struct State<M, R> {
+    // Connections per monitor identity
+    monitors: HashMap<String, Vec<(usize, M)>>,
+    pending: HashMap<String, Pending<R>>,
+    next_id: u64,
+}
+
+impl<M: Clone, R> State<M, R> {
+    fn new() -> Self {
+        Self {
+            monitors: HashMap::new(),
+            pending: HashMap::new(),
+            next_id: 0,
+        }
+    }
+
+    fn register(&mut self, identity: &str, connection: usize, sender: M) {
+        self.monitors
+            .entry(identity.to_owned())
+            .or_default()
+            .push((connection, sender));
+    }
+
+    fn unregister(&mut self, identity: &str, connection: usize) {
+        if let Some(connections) = self.monitors.get_mut(identity) {
+            connections.retain(|(c, _)| *c != connection);
+            if connections.is_empty() {
+                self.monitors.remove(identity);
+            }
+        }
+    }
+
+    // Wait for a reply from the first connection of the monitor, returning
+    // the request with the sender to that connection
+    fn request(
+        &mut self,
+        epoch: u64,
+        monitor: &str,
+        payload: Payload,
+        source: Source,
+        reply_to: R,
+    ) -> Result<(M, Routed), String> {
+        let (connection, sender) = self
+            .monitors
+            .get(monitor)
+            .and_then(|connections| connections.first())
+            .cloned()
+            .ok_or_else(|| format!("Monitor \"{}\" is not connected", monitor))?;
+
+        self.next_id += 1;
+        let correlation_id = format!("{:x}-{:x}", epoch, self.next_id);
+        let pending = Pending {
+            connection,
+            reply_to,
+        };
+        self.pending.insert(correlation_id.clone(), pending);
+        let routed = Routed {
+            correlation_id,
+            connection,
+            payload,
+            source,
+        };
+        Ok((sender, routed))
+    }
+
+    // The request a reply is for, unless it came from another connection
+    fn reply(&mut self, correlation_id: &str, connection: usize) -> Option<Pending<R>> {
+        match self.pending.get(correlation_id) {
+            Some(pending) if pending.connection == connection => self.pending.remove(correlation_id),
+            _ => None,
+        }
+    }
+}
+
+// Routes requests from clients to monitors, and their replies back,
+// across threads.
+#[derive(Clone)]
+pub struct Router {
+    state: Arc<Mutex<State<SignalSender<Routed>, SignalSender<Reply>>>>,
+    epoch: u64,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State::new())),
+            epoch: now_millis(),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State<SignalSender<Routed>, SignalSender<Reply>>> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    pub fn register(&self, identity: &str, connection: usize, sender: SignalSender<Routed>) {
+        self.state().register(identity, connection, sender);
+    }
+
+    pub fn unregister(&self, identity: &str, connection: usize) {
+        self.state().unregister(identity, connection);
+    }
+
+    // Send a request to a connection of the monitor, returning its correlation id
+    pub fn request(
+        &self,
+        monitor: &str,
+        payload: Payload,
+        source: Source,
+        reply_to: SignalSender<Reply>,
+    ) -> Result<String, String> {
+        let mut state = self.state();
+        let (sender, routed) = state.request(self.epoch, monitor, payload, source, reply_to)?;
+        let correlation_id = routed.correlation_id.clone();
+        if sender.send(routed).is_err() {
+            state.pending.remove(&correlation_id);
+            return Err(format!("Monitor \"{}\" is not connected", monitor));
+        }
+        Ok(correlation_id)
+    }
+
+    // Pass a monitor's reply back to the client. Replies to requests that
+    // timed out, were never made or went to another connection are dropped.
+    pub fn reply(&self, correlation_id: &str, connection: usize, message: Message) {
+        if let Some(pending) = self.state().reply(correlation_id, connection) {
+            let reply = Reply {
+                correlation_id: correlation_id.to_owned(),
+                message,
+            };
+            let _ = pending.reply_to.send(reply);
+        }
+    }
+
+    // Stop waiting for a reply, returning whether it was still pending
+    pub fn cancel(&self, correlation_id: &str) -> bool {
+        self.state().pending.remove(correlation_id).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source() -> Source {
+        Source {
+            identity: Some("dashboard".into()),
+            peer: None,
+        }
+    }
+
+    // Replies go to the client numbered `client`
+    fn request(state: &mut State<&'static str, usize>, monitor: &str, client: usize) -> Result<(&'static str, Routed), String> {
+        state.request(1, monitor, "rerun".into(), source(), client)
+    }
+
+    #[test]
+    fn routes_to_the_first_connection() {
+        let mut state = State::new();
+        state.register("web1", 3, "first");
+        state.register("web1", 7, "second");
+        state.register("db1", 9, "db");
+
+        let (sender, routed) = request(&mut state, "web1", 1).unwrap();
+        assert_eq!(sender, "first");
+        assert_eq!(routed.connection, 3);
+        assert_eq!(routed.payload, Payload::from("rerun"));
+        assert_eq!(routed.source.identity.as_deref(), Some("dashboard"));
+
+        state.unregister("web1", 3);
+        let (sender, routed) = request(&mut state, "web1", 1).unwrap();
+        assert_eq!(sender, "second");
+        assert_eq!(routed.connection, 7);
+
+        state.unregister("web1", 7);
+        assert!(request(&mut state, "web1", 1).is_err());
+        assert!(!state.monitors.contains_key("web1"));
+    }
+
+    #[test]
+    fn correlates_replies() {
+        let mut state = State::new();
+        state.register("web1", 3, "web1");
+
+        let (_, first) = request(&mut state, "web1", 1).unwrap();
+        let (_, second) = request(&mut state, "web1", 2).unwrap();
+        assert_ne!(first.correlation_id, second.correlation_id);
+        assert_eq!(first.correlation_id, "1-1");
+
+        let pending = state.reply(&second.correlation_id, 3).unwrap();
+        assert_eq!(pending.reply_to, 2);
+        // Only the first reply is passed on
+        assert!(state.reply(&second.correlation_id, 3).is_none());
+        assert!(state.reply("1-ff", 3).is_none());
+
+        let pending = state.reply(&first.correlation_id, 3).unwrap();
+        assert_eq!(pending.reply_to, 1);
+    }
+
+    #[test]
+    fn rejects_replies_from_other_connections() {
+        let mut state = State::new();
+        state.register("web1", 3, "first");
+        state.register("web1", 7, "second");
+
+        let (_, routed) = request(&mut state, "web1", 1).unwrap();
+        assert!(state.reply(&routed.correlation_id, 7).is_none());
+        assert!(state.reply(&routed.correlation_id, 3).is_some());
+    }
+}
@@ -2,6 +2,7 @@ use std::fs::remove_file;
 use std::io::{Read, Write};
 use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
 
 use sonr::errors::Result;
 use sonr::net::tcp::ReactiveTcpListener;
@@ -12,12 +13,22 @@ use sonr::sync::queue::{ReactiveDeque, ReactiveQueue};
 use sonr::Evented;
 use sonr_tls::TlsAcceptor;
 
-use crate::auth::{AuthMessage, Authentication};
-use crate::clients::Clients;
+use crate::aggregates::Aggregates;
+use crate::alerts::{Anomalies, Deadman, Rules, Silences};
+use crate::auth::{AuthMessage, Authentication, Handshake, Negotiation, Session};
+use crate::clients::{Clients, Context, Request, Sessions};
 use crate::codecs::LineCodec;
 use crate::config::{Config, Optional};
+use crate::durable::{Durables, Queues};
+use crate::history::History;
 use crate::messages::Message;
 use crate::monitors::Monitors;
+use crate::presence::Presence;
+use crate::publisher::Publisher;
+use crate::ratelimit::RateLimiter;
+use crate::router::Router;
+use crate::rollups::{Rollups, Store};
+use crate::sinks::Sinks;
 use crate::throttle::ThrottledOutput;
 
 fn tcp_listener(host: &str) -> ReactiveTcpListener {
@@ -34,10 +45,39 @@ fn tls<T: Evented + Read + Write>(config: &Config) -> TlsAcceptor<T> {
 }
 
 pub fn serve(config: Config) -> Result<()> {
+    config.check()?;
     let config = Arc::new(config);
     System::init()?;
 
     let broadcast = Broadcast::unbounded();
+    let silences = Silences::new(config.data_path("silences.json"))?;
+    let history = History::new(Duration::from_secs(config.sessions.retention), config.sessions.max_messages);
+    let sessions = Sessions::new(&config.sessions);
+    let publisher = Publisher::new(broadcast.clone(), silences.clone(), history.clone());
+    let presence = Presence::new(publisher.clone());
+    let rate_limiter = RateLimiter::new(config.publish_limits.clone());
+    let router = Router::new();
+    let deadman = Deadman::new(broadcast.subscriber(), publisher.clone(), &config.deadman)?;
+    let rules = Rules::new(broadcast.subscriber(), publisher.clone(), &config.rules)?;
+    let anomalies = Anomalies::new(broadcast.subscriber(), publisher.clone(), &config.anomalies)?;
+    let sinks = Sinks::new(broadcast.subscriber(), silences.clone(), &config.sinks)?;
+    let aggregates = Aggregates::new(broadcast.subscriber(), publisher.clone(), &config.aggregates)?;
+    let rollups = match config.rollups.is_enabled() {
+        true => {
+            let store = Store::new(config.required_data_path("rollups", "rollups")?, config.rollups.tiers.clone())?;
+            Some(Rollups::new(broadcast.subscriber(), store, &config.rollups)?)
+        }
+        false => None,
+    };
+    let queries = rollups.as_ref().map(Rollups::queries);
+    let durables = match config.durables.is_empty() {
+        true => None,
+        false => {
+            let dir = config.required_data_path("durable", "durable subscriptions")?;
+            Some(Durables::new(broadcast.subscriber(), &config.durables, &dir)?)
+        }
+    };
+    let queues = durables.as_ref().map_or_else(Queues::default, Durables::queues);
 
     // Tcp client
     let tcp_listener_client =
@@ -70,6 +110,18 @@ pub fn serve(config: Config) -> Result<()> {
         let uds_monitor_deque = uds_monitor_queue.deque();
         let config = config.clone();
         let monitor = broadcast.clone();
+        let context = Context {
+            publisher: publisher.clone(),
+            rate_limiter: rate_limiter.clone(),
+            presence: presence.clone(),
+            silences: silences.clone(),
+            queries: queries.clone(),
+            router: router.clone(),
+            history: history.clone(),
+            sessions: sessions.clone(),
+            queues: queues.clone(),
+            config: config.clone(),
+        };
         thread::spawn(move || -> Result<()> {
             System::init()?;
 
@@ -78,29 +130,45 @@ pub fn serve(config: Config) -> Result<()> {
             let cli_authentication = Authentication::<_, LineCodec<AuthMessage>, _>::new(
                 config.clone(),
                 Some(tcp_client_throttle),
-            );
-            let tcp_cli = Clients::<_, LineCodec<Message>>::new(monitor.subscriber())?;
+            )?;
+            let tcp_cli = Clients::<_, LineCodec<Request>>::new(monitor.subscriber(), context.clone())?;
 
             // Uds clients
-            let uds_client_deque = ReactiveDeque::new(uds_client_deque)?.map(|s| Stream::new(s).unwrap());
-            let uds_cli = Clients::<_, LineCodec<Message>>::new(monitor.subscriber())?;
+            let uds_client_deque = ReactiveDeque::new(uds_client_deque)?
+                .map(|s| Session::unauthenticated(Stream::new(s).unwrap()));
+            let cli_negotiation = Negotiation::<_, LineCodec<Handshake>>::new(config.clone());
+            let uds_cli = Clients::<_, LineCodec<Request>>::new(monitor.subscriber(), context.clone())?;
 
             // Tcp monitors
             let tcp_monitor_deque = ReactiveDeque::new(tcp_monitor_deque)?.map(|s| Stream::new(s).unwrap());
             let mon_authentication = Authentication::<_, LineCodec<AuthMessage>, _>::new(
                 config.clone(),
                 Some(tcp_monitor_throttle),
-            );
-            let tcp_mon = Monitors::<_, LineCodec<Message>>::new(monitor.clone());
+            )?;
+            let tcp_mon = Monitors::<_, LineCodec<Message>>::new(
+                context.publisher.clone(),
+                context.presence.clone(),
+                context.rate_limiter.clone(),
+                context.router.clone(),
+                config.clone(),
+            )?;
 
             // Uds monitors
-            let uds_monitor_deque = ReactiveDeque::new(uds_monitor_deque)?.map(|s| Stream::new(s).unwrap());
-            let uds_mon = Monitors::<_, LineCodec<Message>>::new(monitor);
+            let uds_monitor_deque = ReactiveDeque::new(uds_monitor_deque)?
+                .map(|s| Session::unauthenticated(Stream::new(s).unwrap()));
+            let mon_negotiation = Negotiation::<_, LineCodec<Handshake>>::new(config.clone());
+            let uds_mon = Monitors::<_, LineCodec<Message>>::new(
+                context.publisher,
+                context.presence,
+                context.rate_limiter,
+                context.router,
+                config.clone(),
+            )?;
 
             let tcp_client_run = tcp_client_deque.chain(tls(&config).chain(cli_authentication.chain(tcp_cli)));
-            let uds_client_run = uds_client_deque.chain(uds_cli);
+            let uds_client_run = uds_client_deque.chain(cli_negotiation.chain(uds_cli));
             let tcp_monitor_run = tcp_monitor_deque.chain(tls(&config).chain(mon_authentication.chain(tcp_mon)));
-            let uds_monitor_run = uds_monitor_deque.chain(uds_mon);
+            let uds_monitor_run = uds_monitor_deque.chain(mon_negotiation.chain(uds_mon));
 
             System::start(
                 tcp_client_run
@@ -132,7 +200,14 @@ pub fn serve(config: Config) -> Result<()> {
         tcp_client_run
             .and(tcp_monitor_run)
             .and(uds_client_run)
-            .and(uds_monitor_run),
+            .and(uds_monitor_run)
+            .and(deadman)
+            .and(rules)
+            .and(anomalies)
+            .and(sinks)
+            .and(aggregates)
+            .and(Optional::from(rollups))
+            .and(Optional::from(durables)),
     )?;
     Ok(())
 }
@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use native_tls::TlsConnector;
+
+// Just enough of http to post a webhook
+#[derive(Debug, Clone)]
+pub struct Url {
+    tls: bool,
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> Result<Url, String> {
+        let (tls, rest) = match (url.strip_prefix("https://"), url.strip_prefix("http://")) {
+            (Some(rest), _) => (true, rest),
+            (None, Some(rest)) => (false, rest),
+            (None, None) => return Err(format!("unsupported url \"{}\"", url)),
+        };
+
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rfind(':') {
+            Some(i) => {
+                let port = authority[i + 1..]
+                    .parse()
+                    .map_err(|_| format!("invalid port in url \"{}\"", url))?;
+                (&authority[..i], port)
+            }
+            None => (authority, if tls { 443 } else { 80 }),
+        };
+        if host.is_empty() {
+            return Err(format!("missing host in url \"{}\"", url));
+        }
+
+        Ok(Url {
+            tls,
+            host: host.to_owned(),
+            port,
+            path: path.to_owned(),
+        })
+    }
+}
+
+fn other<E: ToString>(e: E) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+// Post a json body, returning the status code of the response
+pub fn post(url: &Url, headers: &HashMap<String, String>, body: &[u8], timeout: Duration) -> io::Result<u16> {
+    let addr = (url.host.as_str(), url.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| other(format!("can not resolve {}", url.host)))?;
+    let stream = TcpStream::connect_timeout(&addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    match url.tls {
+        true => {
+            let connector = TlsConnector::new().map_err(other)?;
+            let stream = connector.connect(&url.host, stream).map_err(other)?;
+            request(stream, url, headers, body)
+        }
+        false => request(stream, url, headers, body),
+    }
+}
+
+fn request<S: Read + Write>(mut stream: S, url: &Url, headers: &HashMap<String, String>, body: &[u8]) -> io::Result<u16> {
+    let mut head = format!(
+        "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        url.path,
+        url.host,
+        url.port,
+        body.len()
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body)?;
+    stream.flush()?;
+
+    // HTTP/1.1 200 OK
+    let mut status = String::new();
+    BufReader::new(stream).read_line(&mut status)?;
+    status
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "invalid http response"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn parses_urls() {
+        let url = Url::parse("https://hooks.example.com/remonitor?team=ops").unwrap();
+        assert!(url.tls);
+        assert_eq!((url.host.as_str(), url.port, url.path.as_str()), ("hooks.example.com", 443, "/remonitor?team=ops"));
+
+        let url = Url::parse("http://10.0.0.5:8080").unwrap();
+        assert!(!url.tls);
+        assert_eq!((url.host.as_str(), url.port, url.path.as_str()), ("10.0.0.5", 8080, "/"));
+
+        assert!(Url::parse("ftp://example.com/").is_err());
+        assert!(Url::parse("http://example.com:http/").is_err());
+        assert!(Url::parse("http:///hook").is_err());
+    }
+
+    #[test]
+    fn posts_the_body() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut head = Vec::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                head.push(line.trim_end().to_owned());
+            }
+            let length: usize = head
+                .iter()
+                .find_map(|line| line.strip_prefix("Content-Length: "))
+                .unwrap()
+                .parse()
+                .unwrap();
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            reader.get_mut().write_all(b"HTTP/1.1 202 Accepted\r\nContent-Length: 0\r\n\r\n").unwrap();
+            (head, body)
+        });
+
+        let url = Url::parse(&format!("http://127.0.0.1:{}/hook", port)).unwrap();
+        let mut headers = HashMap::new();
+        headers.insert("Authorization".to_owned(), "Bearer secret".to_owned());
+        let status = post(&url, &headers, b"{\"alert\":\"high_cpu\"}", Duration::from_secs(5)).unwrap();
+        assert_eq!(status, 202);
+
+        let (head, body) = server.join().unwrap();
+        assert_eq!(head[0], "POST /hook HTTP/1.1");
+        assert!(head.contains(&"Authorization: Bearer secret".to_owned()));
+        assert!(head.contains(&"Content-Type: application/json".to_owned()));
+        assert_eq!(body, b"{\"alert\":\"high_cpu\"}");
+    }
+}
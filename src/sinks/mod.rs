@@ -0,0 +1,286 @@
+use std::cmp::min;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::error;
+use serde_derive::Deserialize;
+use sonr::errors::Result;
+use sonr::reactor::{Reaction, Reactor};
+use sonr::sync::signal::{ReactiveSignalReceiver, SignalReceiver};
+
+use crate::alerts::Silences;
+use crate::filter::Filter;
+use crate::messages::Message;
+
+mod http;
+mod template;
+use http::Url;
+use template::Template;
+
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+// How often a running command is checked on
+const EXEC_POLL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SinkConfig {
+    pub name: String,
+    // Post to a webhook
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    // Json body template, the message itself if left out
+    #[serde(default)]
+    pub body: Option<String>,
+    // Or run a command with the message on stdin
+    #[serde(default)]
+    pub command: Option<Vec<String>>,
+    // The messages to deliver, a filter expression
+    #[serde(default = "default_filter")]
+    pub filter: String,
+    // Seconds
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    // Seconds before the first retry, doubled for every retry after it
+    #[serde(default = "default_backoff")]
+    pub backoff: u64,
+    // Messages waiting to be delivered, anything more is dropped
+    #[serde(default = "default_queue_size")]
+    pub queue_size: usize,
+}
+
+fn default_filter() -> String {
+    "channel == \"ALERTS\" || message_type == \"error\"".into()
+}
+
+fn default_timeout() -> u64 {
+    10
+}
+
+fn default_retries() -> u32 {
+    5
+}
+
+fn default_backoff() -> u64 {
+    1
+}
+
+fn default_queue_size() -> usize {
+    1000
+}
+
+enum Target {
+    Webhook {
+        url: Url,
+        headers: HashMap<String, String>,
+        body: Option<Template>,
+        timeout: Duration,
+    },
+    Exec {
+        command: Vec<String>,
+        timeout: Duration,
+    },
+}
+
+impl Target {
+    fn new(config: &SinkConfig) -> std::result::Result<Target, String> {
+        match (&config.url, &config.command) {
+            (Some(url), None) => Ok(Target::Webhook {
+                url: Url::parse(url)?,
+                headers: config.headers.clone(),
+                body: match config.body {
+                    Some(ref body) => Some(Template::parse(body)?),
+                    None => None,
+                },
+                timeout: Duration::from_secs(config.timeout),
+            }),
+            (None, Some(command)) if !command.is_empty() => Ok(Target::Exec {
+                command: command.clone(),
+                timeout: Duration::from_secs(config.timeout),
+            }),
+            _ => Err("exactly one of url and command must be set".into()),
+        }
+    }
+
+    fn deliver(&self, message: &Message) -> std::result::Result<(), String> {
+        match self {
+            Target::Webhook { url, headers, body, timeout } => {
+                let body = match body {
+                    Some(template) => template.render(message),
+                    None => serde_json::to_value(message).map_err(|e| e.to_string())?,
+                };
+                let body = serde_json::to_vec(&body).map_err(|e| e.to_string())?;
+                match http::post(url, headers, &body, *timeout) {
+                    Ok(status) if (200..300).contains(&status) => Ok(()),
+                    Ok(status) => Err(format!("http status {}", status)),
+                    Err(e) => Err(e.to_string()),
+                }
+            }
+            Target::Exec { command, timeout } => exec(command, message, *timeout).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+// Run the command, killing it if it runs for longer than `timeout`
+fn exec(command: &[String], message: &Message, timeout: Duration) -> io::Result<()> {
+    let mut child = Command::new(&command[0])
+        .args(&command[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        let mut line = serde_json::to_vec(message)?;
+        line.push(b'\n');
+        stdin.write_all(&line)?;
+    }
+
+    // A timeout too large for an instant never expires
+    let deadline = Instant::now().checked_add(timeout);
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            let _ = child.kill();
+            child.wait()?;
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "command timed out"));
+        }
+        thread::sleep(EXEC_POLL);
+    };
+    match status.success() {
+        true => Ok(()),
+        false => Err(io::Error::other(format!("command exited with {}", status))),
+    }
+}
+
+// Delivers messages one at a time, in order, retrying each with backoff
+fn deliver(name: String, target: Target, retries: u32, backoff: Duration, queue: Receiver<Arc<Message>>) {
+    for message in queue {
+        let mut delay = backoff;
+        for attempt in 0..=retries {
+            match target.deliver(&message) {
+                Ok(()) => break,
+                Err(reason) if attempt < retries => {
+                    error!("Sink \"{}\" failed, retrying in {:?}: {}", name, delay, reason);
+                    thread::sleep(delay);
+                    delay = min(delay * 2, MAX_BACKOFF);
+                }
+                Err(reason) => error!("Sink \"{}\" failed, dropping message: {}", name, reason),
+            }
+        }
+    }
+}
+
+struct Sink {
+    name: String,
+    filter: Filter,
+    queue: SyncSender<Arc<Message>>,
+    full: bool,
+}
+
+// Hands matching messages to a delivery thread per sink, so slow or
+// failing endpoints never hold up the server.
+pub struct Sinks {
+    receiver: ReactiveSignalReceiver<Arc<Message>>,
+    silences: Silences,
+    sinks: Vec<Sink>,
+}
+
+impl Sinks {
+    pub fn new(receiver: SignalReceiver<Arc<Message>>, silences: Silences, configs: &[SinkConfig]) -> Result<Self> {
+        let mut sinks = Vec::new();
+        for config in configs {
+            let invalid = |reason| {
+                let reason = format!("invalid sink \"{}\": {}", config.name, reason);
+                io::Error::new(io::ErrorKind::InvalidInput, reason)
+            };
+            let target = Target::new(config).map_err(invalid)?;
+            let filter = Filter::parse(&config.filter).map_err(invalid)?;
+
+            let (queue, pending) = sync_channel(config.queue_size);
+            let name = config.name.clone();
+            let backoff = Duration::from_secs(config.backoff);
+            let retries = config.retries;
+            thread::spawn(move || deliver(name, target, retries, backoff, pending));
+
+            sinks.push(Sink {
+                name: config.name.clone(),
+                filter,
+                queue,
+                full: false,
+            });
+        }
+
+        Ok(Self {
+            receiver: ReactiveSignalReceiver::new(receiver)?,
+            silences,
+            sinks,
+        })
+    }
+}
+
+impl Reactor for Sinks {
+    type Input = ();
+    type Output = ();
+
+    fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
+        match reaction {
+            Reaction::Event(event) => {
+                if event.token() != self.receiver.token() {
+                    return event.into();
+                }
+
+                while let Ok(message) = self.receiver.try_recv() {
+                    if self.sinks.is_empty() || self.silences.is_silenced(&message) {
+                        continue;
+                    }
+                    for sink in self.sinks.iter_mut().filter(|s| s.filter.matches(&message)) {
+                        match sink.queue.try_send(message.clone()) {
+                            Ok(()) => sink.full = false,
+                            Err(TrySendError::Full(_)) => {
+                                if !sink.full {
+                                    error!("Sink \"{}\" queue is full, dropping messages", sink.name);
+                                }
+                                sink.full = true;
+                            }
+                            Err(TrySendError::Disconnected(_)) => {}
+                        }
+                    }
+                }
+                Reaction::Continue
+            }
+            Reaction::Value(()) | Reaction::Continue => Reaction::Continue,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{MessageType, Severity};
+
+    fn command(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn kills_commands_that_time_out() {
+        let message = Message::new("web1", "down".into(), MessageType::Error, Severity::Critical);
+        let timeout = Duration::from_secs(5);
+        assert!(exec(&command(&["cat"]), &message, timeout).is_ok());
+        assert!(exec(&command(&["false"]), &message, timeout).is_err());
+
+        let started = Instant::now();
+        let error = exec(&command(&["sleep", "10"]), &message, Duration::from_millis(200)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,102 @@
+use serde_json::Value;
+
+use crate::messages::Message;
+
+// A json body with `{{path}}` placeholders, resolved against the message
+// with `Message::lookup`. A string that is nothing but a placeholder is
+// replaced by the value itself, any other placeholder by its text.
+//
+// {"text": "{{channel}}: {{payload.alert}} is {{payload.state}}", "value": "{{payload.value}}"}
+#[derive(Debug, Clone)]
+pub struct Template {
+    body: Value,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Template, String> {
+        let body = serde_json::from_str(source).map_err(|e| format!("invalid body template: {}", e))?;
+        Ok(Template { body })
+    }
+
+    pub fn render(&self, message: &Message) -> Value {
+        render(&self.body, message)
+    }
+}
+
+fn render(value: &Value, message: &Message) -> Value {
+    match value {
+        Value::String(s) => match whole_placeholder(s) {
+            Some(path) => message.lookup(&split(path)),
+            None => Value::String(substitute(s, message)),
+        },
+        Value::Array(values) => Value::Array(values.iter().map(|v| render(v, message)).collect()),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(k, v)| (k.clone(), render(v, message)))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+fn split(path: &str) -> Vec<String> {
+    path.trim().split('.').map(String::from).collect()
+}
+
+fn whole_placeholder(s: &str) -> Option<&str> {
+    let inner = s.trim().trim_start_matches("{{").trim_end_matches("}}");
+    match s.trim().starts_with("{{") && s.trim().ends_with("}}") && !inner.contains("{{") {
+        true => Some(inner),
+        false => None,
+    }
+}
+
+fn substitute(s: &str, message: &Message) -> String {
+    let mut out = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end,
+            None => break,
+        };
+        out.push_str(&rest[..start]);
+        match message.lookup(&split(&rest[start + 2..end])) {
+            Value::String(s) => out.push_str(&s),
+            Value::Null => {}
+            value => out.push_str(&value.to_string()),
+        }
+        rest = &rest[end + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{MessageType, Severity};
+    use serde_json::json;
+
+    #[test]
+    fn renders_placeholders() {
+        let payload = json!({ "alert": "high_cpu", "state": "firing", "value": 97.5 });
+        let message = Message::new("ALERTS", payload.into(), MessageType::Error, Severity::Critical);
+        let template = Template::parse(
+            r#"{"text": "{{channel}}: {{ payload.alert }} is {{payload.state}}", "value": "{{payload.value}}",
+                "fields": ["{{severity}}", "at {{payload.value}}{{payload.missing}}", 1], "alert": "{{payload}}"}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            template.render(&message),
+            json!({
+                "text": "ALERTS: high_cpu is firing",
+                "value": 97.5,
+                "fields": ["critical", "at 97.5", 1],
+                "alert": { "alert": "high_cpu", "state": "firing", "value": 97.5 },
+            })
+        );
+        assert!(Template::parse("{\"text\": ").is_err());
+    }
+}
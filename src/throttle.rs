@@ -10,7 +10,6 @@ use sonr::prelude::*;
 use sonr::sync::signal::{ReactiveSignalReceiver, SignalSender};
 use sonr::sync::Capacity;
 
-const MAX_THROTTLE: usize = 1024 * 8;
 const THROTTLE_CHECK: usize = 20;
 
 pub trait ThrottleKey {
@@ -99,12 +98,12 @@ where
                     match self.throttled.get(&key).map(|t| t.expired()) {
                         Some(true) => {
                             self.throttled.remove(&key);
-                            return Reaction::Value(value);
+                            Reaction::Value(value)
                         }
-                        Some(_) | None => return Reaction::Value(value),
+                        Some(_) | None => Reaction::Value(value),
                     }
                 } else {
-                    return Reaction::Value(value);
+                    Reaction::Value(value)
                 }
             }
             Reaction::Event(event) => {
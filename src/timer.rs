@@ -0,0 +1,109 @@
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde_derive::Deserialize;
+use sonr::errors::Result;
+use sonr::sync::signal::ReactiveSignalReceiver;
+use sonr::sync::Capacity;
+
+// Milliseconds since the unix epoch
+pub fn now_millis() -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    now.as_secs() * 1000 + u64::from(now.subsec_millis())
+}
+
+// A signal receiving `()` every `interval`, from a thread that stops
+// once the receiver is dropped.
+pub fn ticker(interval: Duration) -> Result<ReactiveSignalReceiver<()>> {
+    let receiver = ReactiveSignalReceiver::new(Capacity::Unbounded.into())?;
+    let sender = receiver.sender();
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        if sender.send(()).is_err() {
+            break;
+        }
+    });
+    Ok(receiver)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Timeouts {
+    // Seconds without receiving anything before the peer is pinged
+    pub ping_interval: Option<u64>,
+    // Seconds without receiving anything before the connection is closed
+    pub client_idle: Option<u64>,
+    pub monitor_idle: Option<u64>,
+    // Seconds a connection has to authenticate
+    pub auth: Option<u64>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            ping_interval: None,
+            client_idle: None,
+            monitor_idle: None,
+            auth: Some(30),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IdleTimeout {
+    ping_after: Option<Duration>,
+    close_after: Option<Duration>,
+}
+
+impl IdleTimeout {
+    pub fn new(ping_interval: Option<u64>, idle: Option<u64>) -> Self {
+        Self {
+            ping_after: ping_interval.map(Duration::from_secs),
+            close_after: idle.map(Duration::from_secs),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.ping_after.is_some() || self.close_after.is_some()
+    }
+}
+
+pub enum Idle {
+    Active,
+    Ping,
+    Expired,
+}
+
+// When a connection last received anything
+pub struct Liveness {
+    last_seen: Instant,
+    last_ping: Option<Instant>,
+}
+
+impl Liveness {
+    pub fn new() -> Self {
+        Self {
+            last_seen: Instant::now(),
+            last_ping: None,
+        }
+    }
+
+    pub fn seen(&mut self) {
+        self.last_seen = Instant::now();
+        self.last_ping = None;
+    }
+
+    pub fn check(&mut self, timeout: &IdleTimeout) -> Idle {
+        let idle = self.last_seen.elapsed();
+        match (timeout.close_after, timeout.ping_after) {
+            (Some(close), _) if idle > close => Idle::Expired,
+            (_, Some(ping)) if idle > ping && self.last_ping.is_none_or(|p| p.elapsed() > ping) => {
+                self.last_ping = Some(Instant::now());
+                Idle::Ping
+            }
+            _ => Idle::Active,
+        }
+    }
+}